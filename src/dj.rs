@@ -0,0 +1,187 @@
+//! auto-dj keeps the queue filled with songs picked randomly from the library, songs with higher
+//! ratings are picked more often
+use crate::{
+    stats::{RatingConfig, Statistics},
+    store::StatsStore,
+    ConnType,
+};
+use chrono::Utc;
+use clap::Args;
+use log::{debug, error, info, warn};
+use rand::Rng;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+/// Configuration Options for auto-dj
+#[derive(Args, Debug)]
+pub struct AutoDjConfig {
+    /// keep these many upcoming songs in the queue, songs are picked randomly weighted by their
+    /// rating. auto-dj is disabled if not given
+    #[arg(long, env = "MSCOUT_AUTO_DJ")]
+    auto_dj: Option<u32>,
+    /// songs played within these many hours are not picked by auto-dj
+    #[arg(long, default_value_t = 24)]
+    dj_avoid_hours: i64,
+    /// artists of these many last songs in the queue are not picked by auto-dj
+    #[arg(long, default_value_t = 5)]
+    dj_artist_gap: usize,
+}
+
+/// song in the library which can be picked
+#[derive(Debug)]
+struct Candidate {
+    /// path relative to mpd's music directory
+    path: PathBuf,
+    /// artist of the song
+    artist: Option<String>,
+    /// stats of the song
+    stats: Statistics,
+}
+
+/// feeds the queue with songs from the library
+#[derive(Debug)]
+pub struct AutoDj {
+    /// number of upcoming songs to keep in the queue
+    upcoming: u32,
+    /// songs played within this duration are not picked
+    avoid_recent: chrono::Duration,
+    /// number of last songs in the queue whose artists are not picked
+    artist_gap: usize,
+    /// all songs of the library with their stats
+    library: Vec<Candidate>,
+}
+
+impl AutoDj {
+    /// creates auto-dj if it is enabled in the config, library is loaded with the stats from the
+    /// store
+    pub fn new(
+        config: &AutoDjConfig,
+        client: &mut mpd::Client<ConnType>,
+        store: &mut dyn StatsStore,
+    ) -> Option<Self> {
+        let mut dj = Self {
+            upcoming: config.auto_dj?,
+            avoid_recent: chrono::Duration::hours(config.dj_avoid_hours),
+            artist_gap: config.dj_artist_gap,
+            library: Vec::new(),
+        };
+        dj.reload(client, store);
+        Some(dj)
+    }
+
+    /// reloads the songs of the library along with their stats
+    pub fn reload(&mut self, client: &mut mpd::Client<ConnType>, store: &mut dyn StatsStore) {
+        let mut all_stats: HashMap<PathBuf, Statistics> = match store.list(client) {
+            Ok(stats) => stats.into_iter().collect(),
+            Err(err) => {
+                error!("Couldn't get the stats for auto-dj: {:?}", err);
+                HashMap::new()
+            }
+        };
+        match client.listallinfo() {
+            Ok(songs) => {
+                self.library = songs
+                    .into_iter()
+                    .map(|song| {
+                        let path = PathBuf::from(song.file);
+                        Candidate {
+                            stats: all_stats.remove(&path).unwrap_or_default(),
+                            path,
+                            artist: song.artist,
+                        }
+                    })
+                    .collect();
+                info!("auto-dj loaded {} songs", self.library.len());
+            }
+            Err(err) => error!("Couldn't get the songs for auto-dj: {err}"),
+        }
+    }
+
+    /// updates the stats of the song, so that recently played songs are avoided
+    pub fn update(&mut self, path: &Path, stats: Statistics) {
+        if let Some(candidate) = self.library.iter_mut().find(|song| song.path == path) {
+            candidate.stats = stats;
+        }
+    }
+
+    /// adds songs to the queue if there are less upcoming songs than configured
+    pub fn top_up(&self, client: &mut mpd::Client<ConnType>, rating: &RatingConfig) {
+        let (status, queue) = match (client.status(), client.queue()) {
+            (Ok(status), Ok(queue)) => (status, queue),
+            (Err(err), _) | (_, Err(err)) => {
+                error!("{err} while getting the queue for auto-dj");
+                return;
+            }
+        };
+        let played = status.song.map_or(0, |song| song.pos + 1);
+        let upcoming = status.queue_len.saturating_sub(played);
+        if upcoming >= self.upcoming {
+            return;
+        }
+        let queued: HashSet<&str> = queue.iter().map(|song| song.file.as_str()).collect();
+        let mut recent_artists: HashSet<&str> = queue
+            .iter()
+            .rev()
+            .take(self.artist_gap)
+            .filter_map(|song| song.artist.as_deref())
+            .collect();
+        let recent = Utc::now() - self.avoid_recent;
+        let mut candidates: Vec<(&Candidate, f32)> = self
+            .library
+            .iter()
+            .filter(|song| !queued.contains(song.path.to_string_lossy().as_ref()))
+            .filter(|song| song.stats.last_played.is_none_or(|last| last < recent))
+            // +1 so that songs without rating are picked occasionally
+            .map(|song| (song, song.stats.get_ratings(rating).max(0.0) + 1.0))
+            .collect();
+        let mut rng = rand::thread_rng();
+        for _ in upcoming..self.upcoming {
+            // prefer songs of other artists, if there are none then ignore the artist
+            let allowed = |song: &Candidate| {
+                song.artist
+                    .as_deref()
+                    .is_none_or(|artist| !recent_artists.contains(artist))
+            };
+            let pool = if candidates.iter().any(|(song, _)| allowed(song)) {
+                candidates
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (song, _))| allowed(song))
+                    .map(|(idx, (_, weight))| (idx, *weight))
+                    .collect::<Vec<_>>()
+            } else {
+                candidates
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, (_, weight))| (idx, *weight))
+                    .collect()
+            };
+            let total: f32 = pool.iter().map(|(_, weight)| weight).sum();
+            if pool.is_empty() || total <= 0.0 {
+                warn!("auto-dj has no songs to pick");
+                return;
+            }
+            let mut target = rng.gen_range(0.0..total);
+            let picked = pool
+                .iter()
+                .find(|(_, weight)| {
+                    target -= weight;
+                    target < 0.0
+                })
+                .unwrap_or_else(|| pool.last().expect("pool is not empty"))
+                .0;
+            let (song, weight) = candidates.swap_remove(picked);
+            debug!("auto-dj picked {:?} with weight {weight}", song.path);
+            if let Err(err) = client.push(song.path.to_string_lossy().as_ref()) {
+                error!("Couldn't add {:?} to queue due to {err}", song.path);
+                continue;
+            }
+            info!("auto-dj queued {:?}", song.path);
+            if let Some(artist) = song.artist.as_deref() {
+                recent_artists.insert(artist);
+            }
+        }
+    }
+}
@@ -14,6 +14,18 @@ pub enum Error {
     Unknown,
     /// if reading Tag fails from id3
     Id3ReadTag,
+    /// if reading vorbis comments fails from flac or ogg container
+    VorbisReadTag,
+    /// if reading mp4 metadata fails
+    Mp4ReadTag,
+    /// if sqlite database operation fails
+    Database,
+    /// if reading or writing history journal fails
+    History,
+    /// if writing the output fails
+    Output,
+    /// if reading the stats to import fails
+    Import,
 }
 
 /// Custom trait to implement standard expect method but does some logging and exits.
@@ -68,3 +80,12 @@ impl<T> CustomEror<T> for mpd::error::Result<T> {
         })
     }
 }
+
+impl<T> CustomEror<T> for Result<T, Error> {
+    fn try_unwrap(self, err_msg: &str) -> T {
+        self.unwrap_or_else(|err| {
+            error!("{}, {:?}", err_msg, err);
+            exit(1);
+        })
+    }
+}
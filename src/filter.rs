@@ -0,0 +1,342 @@
+//! parses mpd filter expressions like `((artist == "X") AND (album contains 'Y'))` and finds the
+//! matching songs using mpd's find/search commands
+use crate::ConnType;
+use mpd::{search::Operation, Query, Song, Term};
+
+/// comparison of a tag with a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    /// `==`
+    Equals,
+    /// `!=`
+    NotEquals,
+    /// `contains`
+    Contains,
+    /// `starts_with`
+    StartsWith,
+}
+
+/// tags supported by mpd, see `mpd --version`
+const TAGS: &[&str] = &[
+    "artist",
+    "artistsort",
+    "album",
+    "albumsort",
+    "albumartist",
+    "albumartistsort",
+    "title",
+    "titlesort",
+    "track",
+    "name",
+    "genre",
+    "mood",
+    "date",
+    "originaldate",
+    "composer",
+    "composersort",
+    "performer",
+    "conductor",
+    "work",
+    "movement",
+    "movementnumber",
+    "showmovement",
+    "ensemble",
+    "location",
+    "grouping",
+    "comment",
+    "disc",
+    "label",
+    "musicbrainz_artistid",
+    "musicbrainz_albumid",
+    "musicbrainz_albumartistid",
+    "musicbrainz_trackid",
+    "musicbrainz_releasegroupid",
+    "musicbrainz_releasetrackid",
+    "musicbrainz_workid",
+];
+
+/// single `(tag op 'value')` expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Clause {
+    /// name of the tag or `any`, `file`, `base`
+    tag: String,
+    /// comparison operator
+    op: Op,
+    /// value to compare with
+    value: String,
+}
+
+/// filter expression, all of the clauses should match
+#[derive(Debug, Clone)]
+pub struct FilterExpr(Vec<Clause>);
+
+/// cursor over the expression used by the parser
+struct Parser<'a> {
+    /// remaining part of the expression
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    /// skips leading whitespaces
+    fn skip_ws(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    /// consumes `token` if the expression starts with it
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_ws();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// consumes `token` or fails
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(format!("expected `{token}` at `{}`", self.rest))
+        }
+    }
+
+    /// consumes a word till whitespace or parenthesis
+    fn word(&mut self) -> Result<&'a str, String> {
+        self.skip_ws();
+        let end = self
+            .rest
+            .find(|c: char| c.is_whitespace() || c == '(' || c == ')')
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            return Err(format!("expected a word at `{}`", self.rest));
+        }
+        let (word, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Ok(word)
+    }
+
+    /// consumes a value quoted with `'` or `"`, backslash escapes the next character
+    fn quoted(&mut self) -> Result<String, String> {
+        self.skip_ws();
+        let mut chars = self.rest.char_indices();
+        let quote = match chars.next() {
+            Some((_, quote @ ('\'' | '"'))) => quote,
+            _ => return Err(format!("expected quoted value at `{}`", self.rest)),
+        };
+        let mut value = String::new();
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, escaped)) => value.push(escaped),
+                    None => break,
+                },
+                c if c == quote => {
+                    self.rest = &self.rest[idx + c.len_utf8()..];
+                    return Ok(value);
+                }
+                c => value.push(c),
+            }
+        }
+        Err(format!("unterminated value `{}`", self.rest))
+    }
+
+    /// parses expressions joined by `AND` till the end or a closing parenthesis
+    fn and_list(&mut self, clauses: &mut Vec<Clause>) -> Result<(), String> {
+        self.expression(clauses)?;
+        while self.eat("AND") {
+            self.expression(clauses)?;
+        }
+        Ok(())
+    }
+
+    /// parses a parenthesized expression, which is either a clause or a nested `AND` list
+    fn expression(&mut self, clauses: &mut Vec<Clause>) -> Result<(), String> {
+        self.expect("(")?;
+        self.skip_ws();
+        if self.rest.starts_with('(') {
+            self.and_list(clauses)?;
+        } else {
+            let tag = self.word()?;
+            if !["any", "file", "base"]
+                .iter()
+                .chain(TAGS)
+                .any(|known| tag.eq_ignore_ascii_case(known))
+            {
+                return Err(format!("unknown tag `{tag}`"));
+            }
+            // base doesn't have an operator, `(base 'dir')`
+            let op = if tag.eq_ignore_ascii_case("base") {
+                Op::Equals
+            } else {
+                match self.word()? {
+                    "==" => Op::Equals,
+                    "!=" => Op::NotEquals,
+                    "contains" => Op::Contains,
+                    "starts_with" => Op::StartsWith,
+                    op => return Err(format!("unsupported operator `{op}`")),
+                }
+            };
+            clauses.push(Clause {
+                tag: tag.to_string(),
+                op,
+                value: self.quoted()?,
+            });
+        }
+        self.expect(")")
+    }
+}
+
+/// parses the filter expression, only `AND` of the clauses are supported
+pub fn parse_filter(expr: &str) -> Result<FilterExpr, String> {
+    let mut parser = Parser { rest: expr };
+    let mut clauses = Vec::new();
+    parser.and_list(&mut clauses)?;
+    parser.skip_ws();
+    if !parser.rest.is_empty() {
+        return Err(format!("unexpected `{}`", parser.rest));
+    }
+    Ok(FilterExpr(clauses))
+}
+
+impl FilterExpr {
+    /// returns the songs which matches the expression, mpd's `search` is used if `ignore_case`
+    /// otherwise `find`
+    pub fn songs(
+        &self,
+        client: &mut mpd::Client<ConnType>,
+        ignore_case: bool,
+    ) -> mpd::error::Result<Vec<Song>> {
+        let mut query = Query::new();
+        for clause in &self.0 {
+            let term = match clause.tag.to_lowercase().as_str() {
+                "any" => Term::Any,
+                "file" => Term::File,
+                "base" => Term::Base,
+                _ => Term::Tag(clause.tag.as_str().into()),
+            };
+            let op = match clause.op {
+                Op::Equals => Operation::Equals,
+                Op::NotEquals => Operation::NotEquals,
+                Op::Contains => Operation::Contains,
+                Op::StartsWith => Operation::StartsWith,
+            };
+            query.and_with_op(term, op, clause.value.as_str());
+        }
+        if ignore_case {
+            client.search(&query, None)
+        } else {
+            client.find(&query, None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// returns the clauses of the parsed expression
+    fn clauses(expr: &str) -> Vec<(String, Op, String)> {
+        parse_filter(expr)
+            .unwrap()
+            .0
+            .into_iter()
+            .map(|clause| (clause.tag, clause.op, clause.value))
+            .collect()
+    }
+
+    /// returns clause with the tag, op and value
+    fn clause(tag: &str, op: Op, value: &str) -> (String, Op, String) {
+        (tag.to_string(), op, value.to_string())
+    }
+
+    #[test]
+    fn single_clause() {
+        assert_eq!(
+            clauses(r#"(artist == "Daft Punk")"#),
+            [clause("artist", Op::Equals, "Daft Punk")]
+        );
+        assert_eq!(
+            clauses("  ( Genre   !=   'Rock' )  "),
+            [clause("Genre", Op::NotEquals, "Rock")]
+        );
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(
+            clauses("((title contains 'love') AND (file starts_with \"a/\"))"),
+            [
+                clause("title", Op::Contains, "love"),
+                clause("file", Op::StartsWith, "a/"),
+            ]
+        );
+    }
+
+    #[test]
+    fn base_without_operator() {
+        assert_eq!(
+            clauses("(base 'Music/Jazz')"),
+            [clause("base", Op::Equals, "Music/Jazz")]
+        );
+    }
+
+    #[test]
+    fn quotes_and_escapes() {
+        assert_eq!(
+            clauses(r#"(album == 'Rock\'n\'Roll (Live)')"#),
+            [clause("album", Op::Equals, "Rock'n'Roll (Live)")]
+        );
+        assert_eq!(
+            clauses(r#"(title == "say \"hi\" \\ bye")"#),
+            [clause("title", Op::Equals, r#"say "hi" \ bye"#)]
+        );
+        assert_eq!(
+            clauses("(title == 'caf\u{e9} \"ol\u{e9}\"')"),
+            [clause("title", Op::Equals, "caf\u{e9} \"ol\u{e9}\"")]
+        );
+    }
+
+    #[test]
+    fn nested_and() {
+        assert_eq!(
+            clauses("(((artist == 'A') AND (album == 'B')) AND (genre == 'C'))"),
+            [
+                clause("artist", Op::Equals, "A"),
+                clause("album", Op::Equals, "B"),
+                clause("genre", Op::Equals, "C"),
+            ]
+        );
+        assert_eq!(
+            clauses("(artist == 'A') AND (musicbrainz_trackid == 'id')"),
+            [
+                clause("artist", Op::Equals, "A"),
+                clause("musicbrainz_trackid", Op::Equals, "id"),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        for expr in [
+            "",
+            "artist == 'A'",
+            "(artist == A)",
+            "(artist == 'A'",
+            "(artist == 'A')) ",
+            "(artist == 'A') trailing",
+            "(artist == 'A') AND",
+            "(artist =~ 'A')",
+            "(artist 'A')",
+            "(artist == 'A)",
+            "(artist == 'A\\')",
+            "((artist == 'A') OR (album == 'B'))",
+            "(artst == 'A')",
+            "(base)",
+        ] {
+            assert!(parse_filter(expr).is_err(), "{expr}");
+        }
+    }
+}
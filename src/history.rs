@@ -0,0 +1,248 @@
+//! journal of every played and skipped song. Each event is appended as a json line to the history
+//! file, so that listening history can be queried later.
+use crate::error::Error;
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use clap::{Args, ValueEnum};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// outcome of listening to a song
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    /// song is listened completely
+    Played,
+    /// song is skipped before completing
+    Skipped,
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Played => f.write_str("played"),
+            Outcome::Skipped => f.write_str("skipped"),
+        }
+    }
+}
+
+/// single event in the history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// path of the song relative to mpd's music directory
+    pub path: PathBuf,
+    /// time at which event occurred
+    pub time: DateTime<Utc>,
+    /// seconds the song was listened
+    pub listened: u64,
+    /// whether the song is played or skipped
+    pub outcome: Outcome,
+}
+
+impl HistoryEntry {
+    /// creates new entry of the song with current time
+    pub fn new(path: PathBuf, listened: Duration, outcome: Outcome) -> Self {
+        Self {
+            path,
+            time: Utc::now(),
+            listened: listened.as_secs(),
+            outcome,
+        }
+    }
+}
+
+/// history journal stored in a json lines file
+#[derive(Debug)]
+pub struct History {
+    /// path to the journal file
+    path: PathBuf,
+}
+
+impl History {
+    /// opens history journal at `path`, file is created on first event
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// appends the entry to the end of the journal
+    pub fn append(&self, entry: &HistoryEntry) -> Result<(), Error> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).map_err(|err| {
+                error!("couldn't create directory {:?} due to {err}", parent);
+                Error::History
+            })?;
+        }
+        let line = serde_json::to_string(entry).expect("couldn't convert history entry to json");
+        debug!("appending {line} to history {:?}", self.path);
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{line}"))
+            .map_err(|err| {
+                error!("couldn't write to history {:?} due to {err}", self.path);
+                Error::History
+            })
+    }
+
+    /// returns all the entries of the journal in the order they are appended.
+    /// invalid lines are skipped
+    pub fn entries(&self) -> Result<Vec<HistoryEntry>, Error> {
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                warn!("history {:?} doesn't exist yet", self.path);
+                return Ok(Vec::new());
+            }
+            Err(err) => {
+                error!("couldn't open history {:?} due to {err}", self.path);
+                return Err(Error::History);
+            }
+        };
+        let mut entries = Vec::new();
+        for (line_no, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|err| {
+                error!("couldn't read history {:?} due to {err}", self.path);
+                Error::History
+            })?;
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(err) => warn!(
+                    "skipping invalid history entry at line {}: {err}",
+                    line_no + 1
+                ),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// parses the time given by the user, it can be either date(`2023-08-21`) which is taken as the
+/// start of the day in local timezone, rfc3339 time(`2023-08-21T18:30:00+05:30`) or number of
+/// days(`30d`) or weeks(`4w`) before now
+pub fn parse_time(time: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(time) {
+        return Ok(date_time.with_timezone(&Utc));
+    }
+    let relative = [('d', 1), ('w', 7)].iter().find_map(|(unit, days)| {
+        let count: i64 = time.strip_suffix(*unit)?.parse().ok()?;
+        Some(Utc::now() - chrono::Duration::days(count * days))
+    });
+    if let Some(date_time) = relative {
+        return Ok(date_time);
+    }
+    let date = NaiveDate::parse_from_str(time, "%Y-%m-%d").map_err(|err| {
+        format!("expected date(YYYY-MM-DD), rfc3339 time or days(30d)/weeks(4w) ago: {err}")
+    })?;
+    Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).expect("midnight is a valid time"))
+        .earliest()
+        .map(|date_time| date_time.with_timezone(&Utc))
+        .ok_or_else(|| format!("{time} doesn't exist in local timezone"))
+}
+
+/// Configuration Options for querying history
+#[derive(Args, Debug)]
+pub struct HistoryConfig {
+    /// show events after this time, date(YYYY-MM-DD), rfc3339 time or days(30d)/weeks(4w) ago
+    #[arg(long, value_parser = parse_time)]
+    since: Option<DateTime<Utc>>,
+    /// show events before this time, date(YYYY-MM-DD), rfc3339 time or days(30d)/weeks(4w) ago
+    #[arg(long, value_parser = parse_time)]
+    until: Option<DateTime<Utc>>,
+    /// show only played or skipped events
+    #[arg(value_enum, short, long)]
+    outcome: Option<Outcome>,
+    /// print history in json format
+    #[arg(short, long)]
+    json: bool,
+    /// show events of the songs whose path contains this
+    #[arg()]
+    song: Option<String>,
+}
+
+/// prints the events from the history which matches the query
+pub fn print_history(history: &History, config: &HistoryConfig) -> Result<(), Error> {
+    let entries: Vec<HistoryEntry> = history
+        .entries()?
+        .into_iter()
+        .filter(|entry| config.since.is_none_or(|since| entry.time >= since))
+        .filter(|entry| config.until.is_none_or(|until| entry.time < until))
+        .filter(|entry| {
+            config
+                .outcome
+                .is_none_or(|outcome| entry.outcome == outcome)
+        })
+        .filter(|entry| {
+            config
+                .song
+                .as_deref()
+                .is_none_or(|song| entry.path.to_string_lossy().contains(song))
+        })
+        .collect();
+    if config.json {
+        println!(
+            "{}",
+            serde_json::to_string(&entries).expect("couldn't convert history to json")
+        );
+    } else {
+        for entry in entries {
+            println!(
+                "{}\t{}\t{}s\t{}",
+                entry.time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+                entry.outcome,
+                entry.listened,
+                entry.path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3339_time() {
+        assert_eq!(
+            parse_time("2023-08-21T18:30:00+05:30").unwrap(),
+            DateTime::parse_from_rfc3339("2023-08-21T13:00:00Z").unwrap()
+        );
+    }
+
+    #[test]
+    fn relative_time() {
+        let days = |time: &str| {
+            let time = parse_time(time).unwrap();
+            (Utc::now() - time).num_days()
+        };
+        assert_eq!(days("30d"), 30);
+        assert_eq!(days("4w"), 28);
+        assert_eq!(days("0d"), 0);
+    }
+
+    #[test]
+    fn date_is_local_midnight() {
+        let time = parse_time("2023-08-21").unwrap().with_timezone(&Local);
+        assert_eq!(
+            time.date_naive(),
+            NaiveDate::from_ymd_opt(2023, 8, 21).unwrap()
+        );
+        assert_eq!(time.time(), chrono::NaiveTime::MIN);
+    }
+
+    #[test]
+    fn invalid_time() {
+        for time in ["", "yesterday", "30", "4x", "2023-13-01", "21-08-2023"] {
+            assert!(parse_time(time).is_err(), "{time}");
+        }
+    }
+}
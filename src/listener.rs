@@ -1,6 +1,13 @@
 //! This module handles functions relating listening to events from mpd and setting stats to a song based on the
 //! events
-use crate::{stats, ConnType};
+use crate::{
+    dj::{AutoDj, AutoDjConfig},
+    history::{History, HistoryEntry, Outcome},
+    stats,
+    store::StatsStore,
+    ConnType, MpdAddress,
+};
+use clap::Args;
 // logging macros no need to warn if unused
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
@@ -9,7 +16,7 @@ use notify_rust::{Notification, Urgency};
 use signal_hook::{consts::TERM_SIGNALS, iterator::Signals};
 use std::path::PathBuf;
 use std::process::exit;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 /// alternate to mpd::song::Id with implementation of required traits
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -36,218 +43,429 @@ impl From<Id> for mpd::song::Id {
     }
 }
 
+/// part of mpd's status the listener state depends on
+#[derive(Debug)]
+struct PlayerStatus {
+    /// state of the player
+    state: mpd::State,
+    /// current song in the queue
+    song: Option<Id>,
+    /// single mode is enabled
+    single: bool,
+    /// repeat mode is enabled
+    repeat: bool,
+    /// elapsed time of the current song
+    elapsed: Option<Duration>,
+}
+
+impl From<&mpd::Status> for PlayerStatus {
+    fn from(status: &mpd::Status) -> Self {
+        Self {
+            state: status.state,
+            song: status.song.map(Id::from),
+            single: status.single,
+            repeat: status.repeat,
+            elapsed: status.elapsed,
+        }
+    }
+}
+
+/// details of the song cached when the state changes, so that stats can be recorded even after
+/// mpd removes the song from the queue(e.g. consume mode)
+#[derive(Debug, Clone)]
+struct SongInfo {
+    /// id of the song in the queue
+    id: Id,
+    /// path of the song relative to mpd's music directory
+    file: PathBuf,
+    /// tags of the song
+    tags: Vec<(String, String)>,
+    /// song is skipped by the listener, so it shouldn't be counted as skipped by the user
+    auto_skipped: bool,
+}
+
+impl SongInfo {
+    /// fetches the details of the song from the queue, if song is not found then file is empty
+    fn fetch(client: &mut mpd::Client<ConnType>, id: Id) -> Self {
+        match client.playlistid(id.into()) {
+            Ok(Some(song)) => Self {
+                id,
+                file: PathBuf::from(song.file),
+                tags: song.tags,
+                auto_skipped: false,
+            },
+            res => {
+                error!("Couldn't get the song {:?} from the queue: {:?}", id, res);
+                Self {
+                    id,
+                    file: PathBuf::new(),
+                    tags: Vec::new(),
+                    auto_skipped: false,
+                }
+            }
+        }
+    }
+}
+
 /// specifies last action of the mpd event. It is different from mpd events that mpd events only
 /// mentions subsystems which can't be used to determine the status without some calculations
 #[derive(Debug)]
 enum Action {
-    /// last event skipped the playing song.
-    Skipped(Id),
-    /// last event successfully played complete song
-    Played(Id),
+    /// last event skipped the playing song, with the time it is listened.
+    Skipped(SongInfo, Duration),
+    /// last event successfully played complete song, with the time it is listened
+    Played(SongInfo, Duration),
     /// doesn't matter if other type of event has occurred
     WhoCares,
 }
 
 /// This represents the state of the mpd. This will act as state machine
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum ListenerState {
     /// mpd is Currently Playing.
     Playing {
-        /// curr indicates id of current song
-        curr: (Id, u64),
-        /// next indicates id of next song
-        next: Option<Id>,
-        /// start time of playing
+        /// curr indicates current song and its duration
+        curr: (SongInfo, Duration),
+        /// next indicates next song
+        next: Option<SongInfo>,
+        /// start time of playing, since the last resume or seek
         st: Instant,
+        /// time the current song is listened before `st`
+        listened: Duration,
     },
     /// mpd is in Paused/Stopped state.
     Paused {
-        /// curr indicates id of current song
-        curr: Id,
-        /// next indicates id of next song
-        next: Option<Id>,
+        /// curr indicates current song and its duration
+        curr: (SongInfo, Duration),
+        /// next indicates next song
+        next: Option<SongInfo>,
+        /// time the current song is listened before pausing
+        listened: Duration,
     },
     /// mpd disconnected or there are no songs in the queue/Currently there is no song
     Invalid,
 }
 
+/// decides how long a song should be listened to count it as played or skipped
+#[derive(Args, Debug)]
+pub struct PlayThreshold {
+    /// percentage of the song to be listened to count it as played
+    #[arg(long, env = "MSCOUT_PLAY_PERCENT", default_value_t = 100, value_parser = clap::value_parser!(u8).range(1..=100))]
+    play_percent: u8,
+    /// song is counted as played after listening these many seconds, even if it is less than play-percent
+    #[arg(long, env = "MSCOUT_PLAY_SECS")]
+    play_secs: Option<u64>,
+    /// songs listened less than these many seconds are counted as neither played nor skipped
+    #[arg(long, env = "MSCOUT_MIN_LISTEN", default_value_t = 0)]
+    min_listen: u64,
+}
+
+impl PlayThreshold {
+    /// returns true if the song is listened long enough to count it as played.
+    fn is_played(&self, listened: Duration, duration: Duration) -> bool {
+        if listened.as_secs() < self.min_listen {
+            return false;
+        }
+        let mut threshold = duration.as_secs() * self.play_percent as u64 / 100;
+        if let Some(secs) = self.play_secs {
+            threshold = threshold.min(secs);
+        }
+        // +1 so to eliminate delay introduced by computation, etc
+        listened.as_secs() + 1 >= threshold
+    }
+
+    /// returns whether the song is played or skipped based on the listened time, songs listened
+    /// less than minimum listen time are neither
+    fn action(&self, song: SongInfo, listened: Duration, duration: Duration) -> Action {
+        if self.is_played(listened, duration) {
+            Action::Played(song, listened)
+        } else if listened.as_secs() < self.min_listen {
+            debug!("song {:?} is listened only for {:?}", song.file, listened);
+            Action::WhoCares
+        } else {
+            Action::Skipped(song, listened)
+        }
+    }
+}
+
+/// skips the songs which are skipped often by the user as soon as they start
+#[derive(Args, Debug)]
+pub struct AutoSkip {
+    /// skip the song when it starts if its skip count to total count ratio(0-1) is above this.
+    /// auto-skip is disabled if not given
+    #[arg(long, env = "MSCOUT_AUTO_SKIP", value_parser = parse_ratio)]
+    auto_skip: Option<f32>,
+    /// songs are auto skipped only if they are played or skipped at least these many times
+    #[arg(long, env = "MSCOUT_AUTO_SKIP_MIN", default_value_t = 5)]
+    auto_skip_min: u32,
+}
+
+/// parses the ratio, which should be in range 0-1
+fn parse_ratio(ratio: &str) -> Result<f32, String> {
+    let ratio: f32 = ratio.parse().map_err(|err| format!("{err}"))?;
+    if (0.0..=1.0).contains(&ratio) {
+        Ok(ratio)
+    } else {
+        Err("ratio should be in range 0-1".to_string())
+    }
+}
+
+impl AutoSkip {
+    /// returns true if the song with `stats` should be skipped
+    fn should_skip(&self, stats: &stats::Statistics) -> bool {
+        let Some(ratio) = self.auto_skip else {
+            return false;
+        };
+        let total = stats.play_cnt + stats.skip_cnt;
+        total > 0 && total >= self.auto_skip_min && stats.skip_cnt as f32 / total as f32 > ratio
+    }
+}
+
+/// Configuration Options for the listener
+#[derive(Args, Debug)]
+pub struct ListenConfig {
+    /// runs the given command whenever statistics changes.
+    /// command should take arguments `path`, `play`, `skip`.
+    /// where path is full path incase of using tags and relative path to mpd dir when using stickers
+    /// command is a jinja template with `path`, `play`, `skip`, `rating` and song's `tags` in the context
+    #[arg(short, long)]
+    action: Option<String>,
+    /// options to compute the rating passed to the action and used by auto-dj
+    #[command(flatten)]
+    rating: stats::RatingConfig,
+    /// options for auto-dj
+    #[command(flatten)]
+    dj: AutoDjConfig,
+    /// threshold for counting the song as played
+    #[command(flatten)]
+    threshold: PlayThreshold,
+    /// options for skipping the songs automatically
+    #[command(flatten)]
+    auto_skip: AutoSkip,
+}
+
 impl ListenerState {
-    /// takes mpd current status and returns Action based on the current state.
-    fn handle_event(&mut self, status: mpd::Status) -> Action {
+    /// returns details of the song with `id`, cached details are used if the song is current or
+    /// next song of the state, otherwise fetched from mpd
+    fn song_info(&self, client: &mut mpd::Client<ConnType>, id: Id) -> SongInfo {
+        let cached = match self {
+            ListenerState::Playing { curr, next, .. }
+            | ListenerState::Paused { curr, next, .. } => [Some(&curr.0), next.as_ref()],
+            ListenerState::Invalid => [None, None],
+        };
+        cached
+            .into_iter()
+            .flatten()
+            .find(|song| song.id == id)
+            .cloned()
+            .unwrap_or_else(|| SongInfo::fetch(client, id))
+    }
+
+    /// returns details of the next song of the status
+    fn next_info(
+        &self,
+        client: &mut mpd::Client<ConnType>,
+        status: &mpd::Status,
+    ) -> Option<SongInfo> {
+        status.nextsong.map(|s| self.song_info(client, s.into()))
+    }
+
+    /// creates new state from the status, `listened` is the time current song of the status is
+    /// already listened
+    fn next_state(
+        &self,
+        client: &mut mpd::Client<ConnType>,
+        status: &mpd::Status,
+        listened: Duration,
+    ) -> Self {
+        if status.state == mpd::State::Stop {
+            return ListenerState::Invalid;
+        }
+        let curr = match (status.song, status.duration) {
+            (Some(song), Some(duration)) => (self.song_info(client, song.into()), duration),
+            (Some(song), None) => {
+                // streams don't have duration, so they can't be played or skipped
+                debug!("status doesn't contain duration of {:?}", song);
+                return ListenerState::Invalid;
+            }
+            (None, _) => return ListenerState::Invalid,
+        };
+        let next = self.next_info(client, status);
+        match status.state {
+            mpd::State::Stop => ListenerState::Invalid,
+            mpd::State::Pause => ListenerState::Paused {
+                curr,
+                next,
+                listened,
+            },
+            mpd::State::Play => ListenerState::Playing {
+                curr,
+                next,
+                st: Instant::now(),
+                listened,
+            },
+        }
+    }
+
+    /// returns the action of the change from this state to `status` and the time the song of
+    /// `status` is already listened.
+    /// Only the time spent in playing state is counted as listened, so pauses and seeks doesn't
+    /// affect whether the song is played or skipped.
+    fn transition(&self, status: &PlayerStatus, threshold: &PlayThreshold) -> (Action, Duration) {
         // here self will be the last state and current state will be in status,
         // so if curr is specified then its last song.
-        match *self {
-            ListenerState::Playing { curr, next, st } => match status.state {
-                mpd::State::Stop => {
-                    info!("{:?} to {:?}", self, status.state);
-                    *self = ListenerState::Invalid;
-                    Action::WhoCares
-                }
-                mpd::State::Pause => {
-                    info!("{:?} to {:?}", self, status.state);
-                    let mut ret = Action::WhoCares;
-                    if let Some(s) = next {
-                        // if single is set then it is possible that state to change from play to paused and song changed
-                        if s.0 == status.song.unwrap().id.0 {
-                            if status.single && st.elapsed().as_secs() + 1 > curr.1 {
-                                // +1 so to eliminate delay introduced by computation, etc
-                                ret = Action::Played(curr.0);
-                            } else {
-                                error!("next song is played when the new state is pause");
-                                debug!("current state: {self:?}, new status: {status:?}");
-                            }
+        info!("{:?} to {:?}", self, status.state);
+        match self.clone() {
+            ListenerState::Playing {
+                curr,
+                next,
+                st,
+                listened,
+            } => {
+                let listened = listened + st.elapsed();
+                let song_changed = status.song != Some(curr.0.id);
+                debug!(
+                    "song {:?} listened for {:?} of {:?}, status {:?}",
+                    curr.0.file, listened, curr.1, status
+                );
+                match status.state {
+                    // at the end of the queue mpd stops after playing the last song
+                    mpd::State::Stop if threshold.is_played(listened, curr.1) => {
+                        (Action::Played(curr.0, listened), Duration::ZERO)
+                    }
+                    mpd::State::Stop => (Action::WhoCares, Duration::ZERO),
+                    mpd::State::Pause => {
+                        let is_next = next.is_some_and(|s| Some(s.id) == status.song);
+                        if is_next && !(status.single && threshold.is_played(listened, curr.1)) {
+                            // if single is set then it is possible that state to change from play to paused and song changed
+                            error!("next song is played when the new state is pause");
+                            debug!("current state: {self:?}, new status: {status:?}");
+                        }
+                        if threshold.is_played(listened, curr.1) {
+                            // if only one song is there in the playlist it is possible that play->pause after completely played
+                            (Action::Played(curr.0, listened), Duration::ZERO)
+                        } else if song_changed {
+                            (Action::WhoCares, Duration::ZERO)
+                        } else {
+                            (Action::WhoCares, listened)
                         }
                     }
-                    if st.elapsed().as_secs() + 1 > curr.1 {
-                        // +1 so to eliminate delay introduced by computation, etc
-                        // if only one song is there in the playlist it is possible that play->pause after completely played
-                        ret = Action::Played(curr.0);
+                    mpd::State::Play if !song_changed => {
+                        // if the current song is same as previous and repeat is enabled then it is possible that song is played
+                        // otherwise song is seeked so continue with listened time
+                        if status.repeat
+                            && threshold.is_played(listened, curr.1)
+                            && status.elapsed.is_some_and(|elapsed| elapsed.as_secs() <= 1)
+                        {
+                            (Action::Played(curr.0, listened), Duration::ZERO)
+                        } else {
+                            (Action::WhoCares, listened)
+                        }
                     }
-                    *self = ListenerState::Paused {
-                        curr: status.song.try_into().unwrap(),
-                        next: status.nextsong.map(|s| s.into()),
-                    };
-                    ret
-                }
-                mpd::State::Play => {
-                    info!("{:?} to {:?}", self, status.state);
-                    let mut ret = Action::WhoCares;
-                    // if the current song is same as previous and repeat is enabled then it is possibl that song is played
-                    if curr.0 == status.song.unwrap().into()
-                        && status.repeat
-                        && st.elapsed().as_secs() + 1 >= curr.1
-                    // +1 to cover some timing errors
-                    {
-                        ret = Action::Played(curr.0);
-                    } else if let Some(n) = next {
+                    mpd::State::Play => {
                         // if the currently playing song is next of previous then either it is skipped or played.
-                        if n == status.song.unwrap().into() {
-                            debug!(
-                                "next {:?}, curr.time:{}, instant : {:?}, and status {:?}",
-                                n, curr.1, st, status
-                            );
-                            if st.elapsed().as_secs() + 1 >= curr.1 {
-                                // +1 so that it will cover if some errors
-                                ret = Action::Played(curr.0);
-                            } else {
-                                ret = Action::Skipped(curr.0);
+                        let action = match next {
+                            Some(n) if Some(n.id) == status.song => {
+                                threshold.action(curr.0, listened, curr.1)
                             }
-                        }
+                            _ => Action::WhoCares,
+                        };
+                        (action, Duration::ZERO)
                     }
-                    *self = ListenerState::Playing {
-                        curr: (
-                            status.song.try_into().unwrap(),
-                            (status.duration.unwrap() - status.elapsed.unwrap()).as_secs(),
-                        ),
-                        next: status.nextsong.map(|s| s.into()),
-                        st: Instant::now(),
-                    };
-                    debug!(
-                        "updating listener {:?}, with elapsed {:?}",
-                        self, status.elapsed
-                    );
-                    ret
                 }
-            },
+            }
             // check if the next is currrent playing song then it is skipped. else just update the state
-            ListenerState::Paused { curr, next } => match status.state {
-                mpd::State::Stop => {
-                    info!("{:?} to {:?}", self, status.state);
-                    *self = ListenerState::Invalid;
-                    Action::WhoCares
-                }
+            ListenerState::Paused {
+                curr,
+                next,
+                listened,
+            } => match status.song {
+                Some(song) if song == curr.0.id => (Action::WhoCares, listened),
                 // it doesn't matter whether it is playing or Paused if the next song is in queue then it is skipped else sequence changed
-                mpd::State::Play | mpd::State::Pause => {
-                    info!("{:?} to {:?}", self, status.state);
-                    *self = ListenerState::Playing {
-                        curr: (
-                            status
-                                .song
-                                .expect("report!!! This shouldn't be None")
-                                .into(),
-                            (status.duration.expect("status doesn't contains time")
-                                - status.elapsed.unwrap())
-                            .as_secs(),
-                        ),
-                        next: status.nextsong.map(|s| s.into()),
-                        st: Instant::now(), // if it started from pause then add the elapsed time
-                    };
-                    debug!(
-                        "updating listener {:?}, with elapsed {:?}",
-                        self, status.elapsed
-                    );
-                    if let Some(s) = next {
-                        if s.0 == status.song.expect("report!!! This should not be NULL").id.0
-                            && !status.single
-                        {
-                            return Action::Skipped(curr);
-                        }
-                    };
-                    Action::WhoCares
+                Some(song)
+                    if next.is_some_and(|s| s.id == song)
+                        && !status.single
+                        && status.state != mpd::State::Stop =>
+                {
+                    (threshold.action(curr.0, listened, curr.1), Duration::ZERO)
                 }
+                _ => (Action::WhoCares, Duration::ZERO),
             },
             // if last state is invalid then whatever happened doesn't matter just update the state and continue
             ListenerState::Invalid => {
-                info!("{:?} to {:?}", self, status.state);
-                match status.state {
-                    mpd::State::Play => {
-                        *self = ListenerState::Playing {
-                            curr: (
-                                status
-                                    .song
-                                    .expect("report!!! This shouldn't be None")
-                                    .into(),
-                                (status.duration.expect("status time is None")
-                                    - status.elapsed.unwrap())
-                                .as_secs(),
-                            ),
-                            next: status.nextsong.map(|s| s.into()),
-                            st: Instant::now(),
-                        };
-                        debug!(
-                            "updating listener {:?}, with elapsed {:?}",
-                            self, status.elapsed
-                        );
-                    }
-                    mpd::State::Pause => {
-                        warn!(
-                            "report!!! This should be unreachable, may lead to undefined behavior"
-                        );
-                        *self = ListenerState::Paused {
-                            curr: status
-                                .song
-                                .try_into()
-                                .expect("report!!! This shouldn't be None"),
-                            next: status.nextsong.map(|s| s.into()),
-                        }
-                    }
-                    mpd::State::Stop => (),
+                if status.state == mpd::State::Pause {
+                    warn!("report!!! This should be unreachable, may lead to undefined behavior");
                 }
+                (Action::WhoCares, Duration::ZERO)
+            }
+        }
+    }
+
+    /// takes mpd current status and returns Action based on the current state.
+    fn handle_event(
+        &mut self,
+        status: mpd::Status,
+        client: &mut mpd::Client<ConnType>,
+        threshold: &PlayThreshold,
+    ) -> Action {
+        let (action, listened) = self.transition(&PlayerStatus::from(&status), threshold);
+        // songs skipped by the listener are neither played nor skipped by the user
+        let action = match action {
+            Action::Played(song, _) | Action::Skipped(song, _) if song.auto_skipped => {
+                debug!("song {:?} is auto skipped", song.file);
                 Action::WhoCares
             }
+            action => action,
+        };
+        *self = self.next_state(client, &status, listened);
+        debug!(
+            "updating listener {:?}, with elapsed {:?}",
+            self, status.elapsed
+        );
+        action
+    }
+    /// returns id of the current song, if there is one
+    fn current_id(&self) -> Option<Id> {
+        match self {
+            ListenerState::Playing { curr, .. } | ListenerState::Paused { curr, .. } => {
+                Some(curr.0.id)
+            }
+            ListenerState::Invalid => None,
         }
     }
+
     /// takes current status of mpd and initiates respective state.
-    fn with_status(status: mpd::Status) -> Self {
-        match status.state {
-            mpd::status::State::Stop => Self::Invalid,
-            mpd::status::State::Pause => Self::Paused {
-                curr: status.song.unwrap().into(),
-                next: status.nextsong.map(|s| s.into()),
-            },
-            mpd::status::State::Play => Self::Playing {
-                curr: (
-                    status.song.try_into().unwrap(),
-                    status
-                        .duration
-                        .expect("status should Contain time")
-                        .as_secs(),
-                ),
-                next: status.nextsong.map(|s| s.into()),
-                st: Instant::now(),
-            },
+    fn with_status(status: mpd::Status, client: &mut mpd::Client<ConnType>) -> Self {
+        Self::Invalid.next_state(client, &status, Duration::ZERO)
+    }
+
+    /// replaces the next song if `current` is still the current song of the state
+    fn set_next(&mut self, current: Option<Id>, new_next: Option<SongInfo>) {
+        match self {
+            ListenerState::Playing { curr, next, .. }
+            | ListenerState::Paused { curr, next, .. }
+                if current == Some(curr.0.id) =>
+            {
+                *next = new_next;
+            }
+            _ => {}
         }
     }
+
+    /// fetches the next song again, since it changes when the queue is modified without any
+    /// player event(e.g. songs added to a queue ending at the current song)
+    fn refresh_next(&mut self, client: &mut mpd::Client<ConnType>) {
+        let status = match client.status() {
+            Ok(status) => status,
+            Err(e) => {
+                error!("{e} while getting the status");
+                return;
+            }
+        };
+        let next = self.next_info(client, &status);
+        self.set_next(status.song.map(Id::from), next);
+        debug!("next song refreshed {:?}", self);
+    }
 }
 
 /// creates a lock file indicating that mscout is running
@@ -313,130 +531,372 @@ fn init_listener(notif: &mut notify_rust::Notification) {
     });
 }
 
-/// runs the action for given song if ID id,
+/// updates the stats of given song based on the outcome,
 /// sends the notification,
 /// runs the user action
-/// `action_str` is used to notify/log message
+/// `rating` is used to compute rating for the user action
+/// returns updated stats of the song if they are saved
 fn action_handle(
-    action_fn: impl Fn(&mut stats::Statistics),
-    id: Id,
-    action_str: &str,
+    outcome: Outcome,
+    song: SongInfo,
     client: &mut mpd::Client<ConnType>,
     notif: &mut notify_rust::Notification,
     usr_action: Option<&minijinja::Template>,
-    use_tags: bool,
-) {
-    if let Ok(Some(song_from_mpd)) = client.playlistid(id.into()) {
-        let song_path = PathBuf::from(song_from_mpd.file);
-        info!("song {action_str} {song_path:?}");
-        notif
-            .body(
-                format!(
-                    "{action_str}: {}",
-                    &song_path
-                        .file_name()
-                        .map_or(song_path.to_str(), |pth| pth.to_str())
-                        .unwrap()
-                )
-                .as_ref(),
+    rating: &stats::RatingConfig,
+    store: &mut dyn StatsStore,
+) -> Option<stats::Statistics> {
+    if song.file.as_os_str().is_empty() {
+        error!(
+            "skipped rating: details of the song {:?} are not known",
+            song.id
+        );
+        return None;
+    }
+    let song_path = song.file;
+    let tags: std::collections::BTreeMap<_, _> = song.tags.into_iter().collect();
+    info!("song {outcome} {song_path:?}");
+    notif
+        .body(
+            format!(
+                "{outcome}: {}",
+                &song_path
+                    .file_name()
+                    .map_or(song_path.to_str(), |pth| pth.to_str())
+                    .unwrap()
             )
-            .show()
-            .ok();
-        // TODO: optimise this in better way
-        let mut stats = if use_tags {
-            stats::stats_from_tag(&song_path)
-        } else {
-            stats::stats_from_sticker(client, &song_path)
-        }
-        .unwrap_or_default();
-        action_fn(&mut stats);
-        match if use_tags {
-            stats::stats_to_tag(&song_path, &stats)
-        } else {
-            stats::stats_to_sticker(client, &song_path, &stats)
-        } {
-            Ok(_) => {
-                if let Some(action) = usr_action {
-                    if let Ok(cmd_str) = action.render(minijinja::context!(path => song_path, play => stats.play_cnt, skip => stats.skip_cnt)){
-                        let mut cmd =std::process::Command::new(cmd_str);
-                        cmd.arg(song_path).arg(format!("{}",stats.play_cnt)).arg(format!("{}",stats.skip_cnt));
-                        info!("Executing user action: {:?}", cmd);
-                        if let Ok(output) = cmd.output(){
-                            info!("command output {output:?}");
-                        }else {
-                            warn!("Failed to launch cmd {:?}", cmd);
-                        }
-                    }else{
-                        warn!("Failed to render command: {:?}", usr_action);
+            .as_ref(),
+        )
+        .show()
+        .ok();
+    let mut stats = store.read(client, &song_path).unwrap_or_default();
+    match outcome {
+        Outcome::Played => stats.played(),
+        Outcome::Skipped => stats.skipped(),
+    }
+    match store.write(client, &song_path, &stats) {
+        Ok(_) => {
+            if let Some(action) = usr_action {
+                if let Ok(cmd_str) = action.render(minijinja::context!(path => song_path, play => stats.play_cnt, skip => stats.skip_cnt, rating => stats.get_ratings(rating), tags => tags)){
+                    let mut cmd =std::process::Command::new(cmd_str);
+                    cmd.arg(song_path).arg(format!("{}",stats.play_cnt)).arg(format!("{}",stats.skip_cnt));
+                    info!("Executing user action: {:?}", cmd);
+                    if let Ok(output) = cmd.output(){
+                        info!("command output {output:?}");
+                    }else {
+                        warn!("Failed to launch cmd {:?}", cmd);
                     }
+                }else{
+                    warn!("Failed to render command: {:?}", usr_action);
                 }
             }
-            Err(_) => {
-                error!("skipped rating: Couldn't set the stats");
+            Some(stats)
+        }
+        Err(_) => {
+            error!("skipped rating: Couldn't set the stats");
+            None
+        }
+    }
+}
+/// maximum delay between reconnection attempts
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// reconnects to mpd, delay between attempts is doubled on every failure.
+/// Blocks until the connection is established
+fn reconnect(address: &MpdAddress, notif: &mut notify_rust::Notification) -> mpd::Client<ConnType> {
+    notif
+        .body("Lost connection to mpd, reconnecting")
+        .show()
+        .ok();
+    let mut delay = Duration::from_secs(1);
+    loop {
+        std::thread::sleep(delay);
+        match address
+            .connect()
+            .map_err(mpd::error::Error::from)
+            .and_then(mpd::Client::new)
+        {
+            Ok(client) => {
+                info!("reconnected to mpd");
+                notif.body("Reconnected to mpd").show().ok();
+                return client;
+            }
+            Err(err) => {
+                warn!("Failed to reconnect to mpd due to {err}, retrying in {delay:?}");
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
             }
         }
-    } else {
-        error!("check if consume is enabled");
     }
 }
+
+/// skips the current song if it is playing and auto-skip says so. Song is marked as auto skipped
+/// so that the state machine doesn't count it as skipped by the user
+fn auto_skip(
+    state: &mut ListenerState,
+    client: &mut mpd::Client<ConnType>,
+    notif: &mut notify_rust::Notification,
+    config: &AutoSkip,
+    store: &mut dyn StatsStore,
+) {
+    let ListenerState::Playing {
+        curr: (song, _), ..
+    } = state
+    else {
+        return;
+    };
+    if config.auto_skip.is_none() || song.file.as_os_str().is_empty() {
+        return;
+    }
+    let stats = match store.read(client, &song.file) {
+        Ok(stats) => stats,
+        Err(err) => {
+            warn!(
+                "Couldn't get the stats of {:?} for auto-skip: {:?}",
+                song.file, err
+            );
+            return;
+        }
+    };
+    if !config.should_skip(&stats) {
+        return;
+    }
+    if let Err(err) = client.next() {
+        error!("{err} while auto skipping {:?}", song.file);
+        return;
+    }
+    song.auto_skipped = true;
+    info!("auto skipped {:?}", song.file);
+    notif
+        .body(
+            format!(
+                "auto skipped: {}",
+                song.file
+                    .file_name()
+                    .unwrap_or(song.file.as_os_str())
+                    .to_string_lossy()
+            )
+            .as_ref(),
+        )
+        .show()
+        .ok();
+}
+
+/// appends the event to the history, failures are only logged so that stats are still updated
+fn record(history: &History, song: &SongInfo, listened: Duration, outcome: Outcome) {
+    if song.file.as_os_str().is_empty() {
+        return;
+    }
+    history
+        .append(&HistoryEntry::new(song.file.clone(), listened, outcome))
+        .unwrap_or_else(|err| warn!("Failed to record history: {:?}", err));
+}
+
 /// listens to mpd events sets the statistics for the song
-/// use_tags: if its true then eyed3 tags will be used else mpd stickers are used to store stats
-pub fn listen(client: &mut mpd::Client<ConnType>, action: Option<&str>, use_tags: bool) -> ! {
+/// address: used to reconnect if the connection to mpd is lost
+/// store: backend where the stats are stored
+/// history: journal where every played and skipped event is appended
+pub fn listen(
+    client: &mut mpd::Client<ConnType>,
+    address: &MpdAddress,
+    config: &ListenConfig,
+    store: &mut dyn StatsStore,
+    history: &History,
+) -> ! {
     let mut notif = Notification::new();
     notif
         .summary("mscout")
         .timeout(10000)
         .urgency(Urgency::Low)
         .icon("/usr/share/icons/Adwaita/scalable/devices/media-optical-dvd-symbolic.svg");
-    let mut state = ListenerState::with_status(client.status().unwrap());
+    let status = client.status().unwrap();
+    let mut state = ListenerState::with_status(status, client);
     init_listener(&mut notif);
     let mut jinja_env = minijinja::Environment::new();
-    let action_tmpl = action.and_then(|ac| {
+    let action_tmpl = config.action.as_deref().and_then(|ac| {
         jinja_env.add_template("action", ac).ok()?;
         jinja_env.get_template("action").ok()
     });
+    let mut dj = AutoDj::new(&config.dj, client, store);
+    if let Some(dj) = &dj {
+        dj.top_up(client, &config.rating);
+        state.refresh_next(client);
+    }
     notif.body("Listener started").show().ok();
     loop {
         match client.wait(&[]) {
             Ok(sub_systems) => {
+                // queue may run low if the song changes or songs are removed from the queue
+                let mut refill = false;
+                // next song has to be fetched again if the queue is modified
+                let mut queue_changed = false;
                 // sub systems which caused the thread to wake up
                 for system in sub_systems {
                     match system {
                         Subsystem::Player => {
+                            refill = true;
                             // let action = eval_player_events(client, &last_state, &start_time, &timer);
-                            match state.handle_event(client.status().unwrap()) {
+                            let status = match client.status() {
+                                Ok(status) => status,
+                                Err(e) => {
+                                    error!("{e} while getting the status");
+                                    continue;
+                                }
+                            };
+                            let last_song = state.current_id();
+                            let action = state.handle_event(status, client, &config.threshold);
+                            if state.current_id() != last_song {
+                                auto_skip(&mut state, client, &mut notif, &config.auto_skip, store);
+                            }
+                            let (song, listened, outcome) = match action {
                                 Action::WhoCares => {
                                     debug!("Someone can't sleep peacefully");
+                                    continue;
                                 }
-                                Action::Played(id) => {
-                                    action_handle(
-                                        stats::Statistics::played,
-                                        id,
-                                        "played",
-                                        client,
-                                        &mut notif,
-                                        action_tmpl.as_ref(),
-                                        use_tags,
-                                    );
+                                Action::Played(song, listened) => (song, listened, Outcome::Played),
+                                Action::Skipped(song, listened) => {
+                                    (song, listened, Outcome::Skipped)
                                 }
-                                Action::Skipped(id) => action_handle(
-                                    stats::Statistics::skipped,
-                                    id,
-                                    "skipped",
-                                    client,
-                                    &mut notif,
-                                    action_tmpl.as_ref(),
-                                    use_tags,
-                                ),
+                            };
+                            record(history, &song, listened, outcome);
+                            let song_path = song.file.clone();
+                            let stats = action_handle(
+                                outcome,
+                                song,
+                                client,
+                                &mut notif,
+                                action_tmpl.as_ref(),
+                                &config.rating,
+                                store,
+                            );
+                            if let (Some(dj), Some(stats)) = (dj.as_mut(), stats) {
+                                dj.update(&song_path, stats);
+                            }
+                        }
+                        Subsystem::Queue => {
+                            refill = true;
+                            queue_changed = true;
+                        }
+                        Subsystem::Database => {
+                            if let Some(dj) = dj.as_mut() {
+                                dj.reload(client, store);
                             }
                         }
                         _ => trace!("ignoring event {}", system),
                     }
                 }
+                if let Some(dj) = dj.as_ref().filter(|_| refill) {
+                    dj.top_up(client, &config.rating);
+                    queue_changed = true;
+                }
+                if queue_changed {
+                    state.refresh_next(client);
+                }
             }
             Err(e) => {
                 error!("{e} while waiting for events");
+                // connection is lost if mpd doesn't respond, so listen on a new connection
+                if client.ping().is_err() {
+                    *client = reconnect(address, &mut notif);
+                    state = match client.status() {
+                        Ok(status) => ListenerState::with_status(status, client),
+                        Err(e) => {
+                            error!("{e} while getting the status");
+                            ListenerState::Invalid
+                        }
+                    };
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// returns stats with play and skip counts
+    fn stats(play_cnt: u32, skip_cnt: u32) -> stats::Statistics {
+        stats::Statistics {
+            play_cnt,
+            skip_cnt,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn auto_skip_needs_minimum_plays_and_skips() {
+        let auto_skip = AutoSkip {
+            auto_skip: Some(0.5),
+            auto_skip_min: 2,
+        };
+        assert!(!auto_skip.should_skip(&stats(0, 1)));
+        assert!(auto_skip.should_skip(&stats(0, 2)));
+        assert!(!auto_skip.should_skip(&stats(1, 1)));
+        assert!(auto_skip.should_skip(&stats(0, 10)));
+        assert!(!auto_skip.should_skip(&stats(10, 10)));
+        assert!(!auto_skip.should_skip(&stats(0, 0)));
+    }
+
+    /// returns song with id `id`
+    fn song(id: u32) -> SongInfo {
+        SongInfo {
+            id: Id(id),
+            file: PathBuf::from(format!("{id}.mp3")),
+            tags: Vec::new(),
+            auto_skipped: false,
+        }
+    }
+
+    #[test]
+    fn next_song_added_to_queue_ending_at_current_song() {
+        let threshold = PlayThreshold {
+            play_percent: 100,
+            play_secs: None,
+            min_listen: 0,
+        };
+        let played = ListenerState::Playing {
+            curr: (song(1), Duration::from_secs(100)),
+            next: None,
+            st: Instant::now(),
+            listened: Duration::from_secs(100),
+        };
+        let status = PlayerStatus {
+            state: mpd::State::Play,
+            song: Some(Id(2)),
+            single: false,
+            repeat: false,
+            elapsed: Some(Duration::ZERO),
+        };
+        // song 2 is appended to the queue after song 1 started
+        let (action, _) = played.transition(&status, &threshold);
+        assert!(matches!(action, Action::WhoCares));
+
+        let mut refreshed = played.clone();
+        refreshed.set_next(Some(Id(1)), Some(song(2)));
+        let (action, _) = refreshed.transition(&status, &threshold);
+        assert!(matches!(action, Action::Played(song, _) if song.id == Id(1)));
+
+        // next of the other song is ignored
+        let mut stale = played;
+        stale.set_next(Some(Id(3)), Some(song(2)));
+        let (action, _) = stale.transition(&status, &threshold);
+        assert!(matches!(action, Action::WhoCares));
+    }
+
+    #[test]
+    fn auto_skip_is_disabled_by_default() {
+        let auto_skip = AutoSkip {
+            auto_skip: None,
+            auto_skip_min: 0,
+        };
+        assert!(!auto_skip.should_skip(&stats(1, 100)));
+    }
+
+    #[test]
+    fn ratio_is_between_0_and_1() {
+        assert_eq!(parse_ratio("0.8"), Ok(0.8));
+        assert_eq!(parse_ratio("1"), Ok(1.0));
+        for invalid in ["-0.1", "1.5", "NaN", "high"] {
+            assert!(parse_ratio(invalid).is_err(), "{invalid}");
+        }
+    }
+}
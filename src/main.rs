@@ -3,9 +3,17 @@
 
 //! This crate provides a way to set or get ratings for songs based on listening statistics.
 //! This is written for mpd as plugin. To work you have to have mpd running.
+mod dj;
 mod error;
+mod filter;
+mod history;
 mod listener;
+mod output;
+mod players;
+mod playlist;
+mod scrobble;
 mod stats;
+mod store;
 use clap::{Parser, Subcommand};
 use color_eyre::eyre::{self, WrapErr};
 use log::{debug, error, trace, warn};
@@ -50,6 +58,30 @@ impl Write for ConnType {
     }
 }
 
+/// address of the mpd, used for connecting and reconnecting to mpd
+#[derive(Debug, Clone)]
+pub struct MpdAddress {
+    /// path to mpd socket file
+    socket_path: String,
+    /// mpd network socket address
+    socket_address: String,
+}
+
+impl MpdAddress {
+    /// connects to mpd through unix socket file, if it fails then through network socket
+    pub fn connect(&self) -> std::io::Result<ConnType> {
+        debug!("trying to connect to unix stream {}", self.socket_path);
+        match std::os::unix::net::UnixStream::connect(&self.socket_path) {
+            Ok(conn) => Ok(ConnType::Stream(conn)),
+            Err(err) => {
+                warn!("Failed to connect to unix stream due to {err}");
+                debug!("connecting to TcpStream {}", self.socket_address);
+                std::net::TcpStream::connect(&self.socket_address).map(ConnType::Socket)
+            }
+        }
+    }
+}
+
 /// contains root dir string optionally either if the user passes through cmdline or if the unix
 /// socket file is given
 static ROOT_DIR: OnceCell<PathBuf> = OnceCell::new();
@@ -59,19 +91,16 @@ static ROOT_DIR: OnceCell<PathBuf> = OnceCell::new();
 enum Commands {
     /// listens for mpd events
     #[command()]
-    Listen {
-        /// runs the given command whenever statistics changes.
-        /// command should take arguments `path`, `play`, `skip`.
-        /// where path is full path incase of using tags and relative path to mpd dir when using stickers
-        #[arg(short, long)]
-        action: Option<String>,
-    },
+    Listen(listener::ListenConfig),
     /// extracts stats of given songs
     #[command()]
     GetStats(stats::GetStatsConfig),
     /// manually set stats for a perticular song, it should be in json
     #[command()]
     SetStats(stats::SetStatsConfig),
+    /// manually rate a song, it overrides the rating computed from stats
+    #[command()]
+    Rate(stats::RateConfig),
     /// export stats to a file
     #[command()]
     Export {
@@ -81,23 +110,25 @@ enum Commands {
         /// exports with songs hash. this way songs name is not required to be matching
         #[arg(short = 'H', long)]
         hash: bool,
+        /// format of the exported stats, only json can be imported back
+        #[arg(value_enum, short, long, default_value_t = output::OutputFormat::Json)]
+        format: output::OutputFormat,
+        /// options to compute the rating for csv and tsv formats
+        #[command(flatten)]
+        rating: stats::RatingConfig,
     },
     /// import stats from a file
     #[command()]
-    Import {
-        /// strategy to import songs
-        #[arg(value_enum, short='M', long, default_value_t=stats::ImportMethodConfig::Path)]
-        method: stats::ImportMethodConfig,
-        /// import stats and if there is already stats available then add both
-        #[arg(short, long)]
-        merge: bool,
-        /// file containing stats, if not present then reads it from stdin
-        #[arg()]
-        input_file: Option<PathBuf>,
-    },
+    Import(stats::ImportConfig),
     /// resets all stats to 0
     #[command()]
     Clear,
+    /// creates playlist of songs selected by their stats
+    #[command()]
+    MakePlaylist(playlist::PlaylistConfig),
+    /// shows history of played and skipped songs
+    #[command()]
+    History(history::HistoryConfig),
 }
 
 /// Arguments for mscout
@@ -110,9 +141,12 @@ struct Config {
     /// sets the verbose level, use multiple times for more verbosity. By default all the logs are written to stderr
     #[arg(short, long, action=clap::ArgAction::Count)]
     verbose: u8,
-    /// use eyed3 tags to store ratings. If not specified by default mpd stickers are used. tags are persistante across file moves, where as incase of mpd sticker these will be erased if you move the files.
-    #[arg(short = 't', long, env = "MSCOUT_USE_TAGS")]
-    use_tags: bool,
+    /// options for the storage backend
+    #[command(flatten)]
+    store: store::StoreConfig,
+    /// path to history journal, where listener appends every played and skipped song
+    #[arg(long, env = "MSCOUT_HISTORY", default_value_t=format!("{}/.local/share/mscout/history.jsonl", std::env::var("HOME").unwrap_or_else(|_|".".to_string())), value_hint(clap::ValueHint::FilePath))]
+    history_file: String,
     /// path to mpd socket.
     /// if both path and socket address are specified, then path has higher priority.
     /// If  this flag is set then music directory is automatically taken from mpd"
@@ -157,71 +191,70 @@ fn main() -> color_eyre::Result<()> {
         }
     }
     debug!("log_level set to {:?}", log::max_level());
-    if arguments.use_tags {
-        debug!("Using tags for storing stats");
-    }
+    let store_kind = arguments.store.kind();
+    debug!("Using {store_kind:?} for storing stats");
 
-    debug!("trying to connect to unix stream {}", arguments.socket_path);
-    let mut client = match std::os::unix::net::UnixStream::connect(arguments.socket_path) {
-        Ok(conn) => {
-            let mut client = mpd::Client::new(ConnType::Stream(conn))
-                .wrap_err("Couldn't create connection to mpd")?;
-            ROOT_DIR
-                .set(PathBuf::from(
-                    client
-                        .music_directory()
-                        .wrap_err("Couldn't get root directory from mpd")?,
-                ))
-                .map_err(|e| eyre::eyre!("Couldn't set root directory: {e:?}"))?;
-            client
-        }
-        Err(err) => {
-            warn!("Failed to connect to unix stream due to {err}");
-            debug!("connecting to TcpStream {}", arguments.socket_address);
-            if arguments.use_tags {
-                if let Some(root_dir) = &arguments.root_dir {
-                    debug!("Setting mpd root-dir to {:?}", root_dir);
-                    ROOT_DIR.set(root_dir.to_path_buf()).map_err(|e| {
-                        color_eyre::eyre::eyre!("Couldn't set root directory to {e:?}")
-                    })?;
-                } else {
-                    error!("for socket connection if tags are required then root-dir must be set");
-                    std::process::exit(1);
-                }
-            }
-            mpd::Client::new(ConnType::Socket(
-                std::net::TcpStream::connect(arguments.socket_address)
-                    .wrap_err("Couldn't create connection to mpd")?,
+    let address = MpdAddress {
+        socket_path: arguments.socket_path,
+        socket_address: arguments.socket_address,
+    };
+    let conn = address
+        .connect()
+        .wrap_err("Couldn't create connection to mpd")?;
+    let is_unix_stream = matches!(conn, ConnType::Stream(_));
+    let mut client = mpd::Client::new(conn).wrap_err("Couldn't create mpd client")?;
+    if is_unix_stream {
+        ROOT_DIR
+            .set(PathBuf::from(
+                client
+                    .music_directory()
+                    .wrap_err("Couldn't get root directory from mpd")?,
             ))
-            .wrap_err("Couldn't create mpd client")?
+            .map_err(|e| eyre::eyre!("Couldn't set root directory: {e:?}"))?;
+    } else if arguments.store.needs_root_dir() {
+        if let Some(root_dir) = &arguments.root_dir {
+            debug!("Setting mpd root-dir to {:?}", root_dir);
+            ROOT_DIR
+                .set(root_dir.to_path_buf())
+                .map_err(|e| color_eyre::eyre::eyre!("Couldn't set root directory to {e:?}"))?;
+        } else {
+            error!(
+                "for socket connection if {store_kind:?} store is used then root-dir must be set"
+            );
+            std::process::exit(1);
         }
-    };
+    }
 
     if let Some(root_dir) = ROOT_DIR.get() {
         std::env::set_var("MPD_DIR", root_dir);
     }
+    let mut store = arguments
+        .store
+        .open()
+        .map_err(|e| eyre::eyre!("Couldn't open {store_kind:?} store: {e:?}"))?;
+    let history = history::History::new(std::path::Path::new(&arguments.history_file));
     match arguments.command {
-        Commands::Listen { action } => {
-            listener::listen(&mut client, action.as_deref(), arguments.use_tags)
+        Commands::Listen(config) => {
+            listener::listen(&mut client, &address, &config, store.as_mut(), &history)
+        }
+        Commands::GetStats(config) => stats::get_stats(&mut client, &config, store.as_mut()),
+        Commands::SetStats(config) => stats::set_stats(&mut client, &config, store.as_mut()),
+        Commands::Rate(config) => stats::rate_song(&mut client, &config, store.as_mut()),
+        Commands::Import(config) => {
+            stats::import_stats(&mut client, &config, store.as_mut(), arguments.yes)
         }
-        Commands::GetStats(config) => stats::get_stats(&mut client, &config, arguments.use_tags),
-        Commands::SetStats(config) => stats::set_stats(&mut client, &config, arguments.use_tags),
-        Commands::Import {
-            method,
-            merge,
-            input_file,
-        } => stats::import_stats(
-            &mut client,
-            method,
-            input_file,
-            merge,
-            arguments.use_tags,
-            arguments.yes,
-        ),
-        Commands::Export { out_file, hash } => {
-            stats::export_stats(&mut client, out_file, hash, arguments.use_tags)
+        Commands::Export {
+            out_file,
+            hash,
+            format,
+            rating,
+        } => stats::export_stats(&mut client, out_file, hash, format, &rating, store.as_mut()),
+        Commands::Clear => stats::clear_stats(&mut client, store.as_mut(), arguments.yes),
+        Commands::MakePlaylist(config) => {
+            playlist::make_playlist(&mut client, &config, store.as_mut())
         }
-        Commands::Clear => stats::clear_stats(&mut client, arguments.use_tags, arguments.yes),
+        Commands::History(config) => history::print_history(&history, &config)
+            .map_err(|e| eyre::eyre!("Couldn't read history: {e:?}"))?,
     }
     Ok(())
 }
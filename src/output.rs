@@ -0,0 +1,67 @@
+//! writes the stats in machine readable formats, so that they can be used in spreadsheets and
+//! shell pipelines
+use crate::error::Error;
+use clap::ValueEnum;
+use log::error;
+use serde::Serialize;
+use std::io::Write;
+
+/// format of the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// comma separated values with a header
+    Csv,
+    /// tab separated values with a header
+    Tsv,
+    /// single json array
+    Json,
+    /// one json object per line
+    Jsonl,
+}
+
+/// logs the error of writing the output
+fn output_error(err: impl std::fmt::Display) -> Error {
+    error!("couldn't write the output due to {err}");
+    Error::Output
+}
+
+/// writes the items to `out` in the format. json and jsonl formats write the items as they are,
+/// csv and tsv formats write the flat rows returned by `row`
+pub fn write_items<T, R>(
+    mut out: impl Write,
+    format: OutputFormat,
+    items: &[T],
+    row: impl Fn(&T) -> R,
+) -> Result<(), Error>
+where
+    T: Serialize,
+    R: Serialize,
+{
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut out, items).map_err(output_error)?;
+            writeln!(out).map_err(output_error)
+        }
+        OutputFormat::Jsonl => {
+            for item in items {
+                serde_json::to_writer(&mut out, item).map_err(output_error)?;
+                writeln!(out).map_err(output_error)?;
+            }
+            Ok(())
+        }
+        OutputFormat::Csv | OutputFormat::Tsv => {
+            let delimiter = if format == OutputFormat::Tsv {
+                b'\t'
+            } else {
+                b','
+            };
+            let mut writer = csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .from_writer(out);
+            for item in items {
+                writer.serialize(row(item)).map_err(output_error)?;
+            }
+            writer.flush().map_err(output_error)
+        }
+    }
+}
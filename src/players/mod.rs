@@ -0,0 +1,84 @@
+//! reads play counts, skip counts and ratings from the libraries of other music players, so that
+//! history is not lost when switching to mpd
+mod pickle;
+mod quodlibet;
+mod rhythmbox;
+mod strawberry;
+
+use crate::{error::Error, stats::Statistics};
+use clap::ValueEnum;
+use std::{
+    ffi::OsString,
+    os::unix::ffi::OsStringExt,
+    path::{Path, PathBuf},
+};
+
+/// music player from whose library the stats are imported
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Player {
+    /// Rhythmbox's `rhythmdb.xml`
+    Rhythmbox,
+    /// Strawberry's or Clementine's sqlite database
+    Strawberry,
+    /// Quod Libet's pickled `songs` library
+    #[value(name = "quodlibet")]
+    QuodLibet,
+}
+
+/// song in the library of the player
+#[derive(Debug)]
+pub struct PlayerSong {
+    /// absolute path of the song as seen by the player
+    pub path: PathBuf,
+    /// title of the song
+    pub title: Option<String>,
+    /// artist of the song
+    pub artist: Option<String>,
+    /// album of the song
+    pub album: Option<String>,
+    /// stats of the song, times and ratings are set if the player tracks them
+    pub stats: Statistics,
+}
+
+/// reads the songs which are played, skipped or rated from the library of the player
+pub fn read_library(player: Player, library: &Path) -> Result<Vec<PlayerSong>, Error> {
+    let songs = match player {
+        Player::Rhythmbox => rhythmbox::read_library(library)?,
+        Player::Strawberry => strawberry::read_library(library)?,
+        Player::QuodLibet => quodlibet::read_library(library)?,
+    };
+    Ok(songs
+        .into_iter()
+        .filter(|song| {
+            song.stats.play_cnt > 0 || song.stats.skip_cnt > 0 || song.stats.user_rating.is_some()
+        })
+        .collect())
+}
+
+/// converts `file://` uri to path, percent encoded bytes are decoded. Other uris are returned as
+/// they are
+fn uri_to_path(uri: &str) -> PathBuf {
+    let Some(path) = uri.strip_prefix("file://") else {
+        return PathBuf::from(uri);
+    };
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    PathBuf::from(OsString::from_vec(bytes))
+}
@@ -0,0 +1,813 @@
+//! minimal reader of python pickles, enough to read the data of the music libraries.
+//! Objects of the classes are read as dictionaries of their items and attributes, since classes
+//! can't be loaded. Containers are shared between the stack and the memo like python objects, so
+//! items added after memoizing are seen by every reference.
+use crate::error::Error;
+use log::{error, trace};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+/// python value read from the pickle
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// `None`
+    None,
+    /// `bool`
+    Bool(bool),
+    /// `int`, which fits in 64 bits
+    Int(i64),
+    /// `float`
+    Float(f64),
+    /// `str`
+    Str(String),
+    /// `bytes` or python2 `str`
+    Bytes(Vec<u8>),
+    /// `list` or `set`
+    List(Vec<Value>),
+    /// `tuple`
+    Tuple(Vec<Value>),
+    /// `dict` or object of a class
+    Dict(Vec<(Value, Value)>),
+    /// class or function, module and name
+    Global(String, String),
+}
+
+impl Value {
+    /// returns the string if the value is `str` or `bytes`, invalid utf-8 is replaced
+    pub fn as_str(&self) -> Option<std::borrow::Cow<'_, str>> {
+        match self {
+            Value::Str(value) => Some(value.into()),
+            Value::Bytes(value) => Some(String::from_utf8_lossy(value)),
+            _ => None,
+        }
+    }
+
+    /// returns the number if the value is `int` or `float`
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(value) => Some(*value as f64),
+            Value::Float(value) => Some(*value),
+            Value::Bool(value) => Some(*value as u8 as f64),
+            _ => None,
+        }
+    }
+
+    /// returns the value of the `key` if the value is a dict
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Dict(items) => items
+                .iter()
+                .find(|(item_key, _)| item_key.as_str().is_some_and(|item_key| item_key == key))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+/// logs the error and converts it to crate error
+fn pickle_error(msg: impl std::fmt::Display) -> Error {
+    error!("invalid pickle: {msg}");
+    Error::Import
+}
+
+/// decodes `\uXXXX` and `\UXXXXXXXX` escapes of raw-unicode-escape encoding
+fn unescape_unicode(raw: &str) -> String {
+    let mut value = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(pos) = rest.find('\\') {
+        value.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let len = match rest.as_bytes().get(1) {
+            Some(b'u') => 4,
+            Some(b'U') => 8,
+            _ => 0,
+        };
+        let decoded = rest
+            .get(2..2 + len)
+            .filter(|_| len > 0)
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(char::from_u32);
+        match decoded {
+            Some(c) => {
+                value.push(c);
+                rest = &rest[2 + len..];
+            }
+            None => {
+                value.push('\\');
+                rest = &rest[1..];
+            }
+        }
+    }
+    value.push_str(rest);
+    value
+}
+
+/// decodes python2 `str` written as its repr in quotes, escapes are same as python's string
+/// literals
+fn unescape_string(repr: &str) -> Result<Vec<u8>, Error> {
+    let quoted = repr.as_bytes();
+    let inner = match quoted {
+        [quote @ (b'\'' | b'"'), inner @ .., end] if end == quote => inner,
+        _ => return Err(pickle_error(format!("invalid string {repr}"))),
+    };
+    let mut value = Vec::with_capacity(inner.len());
+    let mut bytes = inner.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            value.push(byte);
+            continue;
+        }
+        let Some(escape) = bytes.next() else {
+            return Err(pickle_error(format!("invalid escape in {repr}")));
+        };
+        let decoded = match escape {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'r' => b'\r',
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'f' => 0x0c,
+            b'v' => 0x0b,
+            b'x' => {
+                let hex = [bytes.next(), bytes.next()];
+                let hex = match hex {
+                    [Some(high), Some(low)] => [high, low],
+                    _ => return Err(pickle_error(format!("invalid escape in {repr}"))),
+                };
+                std::str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| pickle_error(format!("invalid escape in {repr}")))?
+            }
+            b'0'..=b'7' => {
+                // upto 3 octal digits
+                let mut octal = u32::from(escape - b'0');
+                for _ in 0..2 {
+                    match bytes.peek() {
+                        Some(digit @ b'0'..=b'7') => {
+                            octal = octal * 8 + u32::from(digit - b'0');
+                            bytes.next();
+                        }
+                        _ => break,
+                    }
+                }
+                octal as u8
+            }
+            // `\'`, `\"` and `\\`, unknown escapes are kept as they are like python
+            b'\'' | b'"' | b'\\' => escape,
+            escape => {
+                value.push(b'\\');
+                escape
+            }
+        };
+        value.push(decoded);
+    }
+    Ok(value)
+}
+
+/// python object created by the machine
+#[derive(Debug)]
+enum Object {
+    /// object other than the containers, it is never modified
+    Scalar(Value),
+    /// `list` or `set`
+    List(Vec<Shared>),
+    /// `tuple`
+    Tuple(Vec<Shared>),
+    /// `dict` or object of a class
+    Dict(Vec<(Shared, Shared)>),
+}
+
+/// reference to the object, same object is referenced from the stack, memo and containers
+type Shared = Rc<RefCell<Object>>;
+
+/// creates new object
+fn shared(object: Object) -> Shared {
+    Rc::new(RefCell::new(object))
+}
+
+/// creates new scalar object
+fn scalar(value: Value) -> Shared {
+    shared(Object::Scalar(value))
+}
+
+/// converts the object to value, containers which contain themselves are cut at the recursion
+/// and read as `None`
+fn to_value(object: &Shared, parents: &mut Vec<*const RefCell<Object>>) -> Value {
+    if parents.contains(&Rc::as_ptr(object)) {
+        trace!("recursive object found");
+        return Value::None;
+    }
+    parents.push(Rc::as_ptr(object));
+    let mut items = |items: &[Shared]| -> Vec<Value> {
+        items.iter().map(|item| to_value(item, parents)).collect()
+    };
+    let value = match &*object.borrow() {
+        Object::Scalar(value) => value.clone(),
+        Object::List(list) => Value::List(items(list)),
+        Object::Tuple(tuple) => Value::Tuple(items(tuple)),
+        Object::Dict(dict) => Value::Dict(
+            dict.iter()
+                .map(|(key, value)| (to_value(key, parents), to_value(value, parents)))
+                .collect(),
+        ),
+    };
+    parents.pop();
+    value
+}
+
+/// item on the stack of the pickle machine
+#[derive(Debug)]
+enum Item {
+    /// marks the start of the items of the list, tuple or dict
+    Mark,
+    /// object
+    Object(Shared),
+}
+
+/// state of the pickle machine
+struct Machine<'a> {
+    /// remaining data of the pickle
+    data: &'a [u8],
+    /// stack of the machine
+    stack: Vec<Item>,
+    /// memoized objects
+    memo: HashMap<u64, Shared>,
+}
+
+impl<'a> Machine<'a> {
+    /// takes next `len` bytes
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            return Err(pickle_error("unexpected end of data"));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    /// takes next `N` bytes as array
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        Ok(self.take(N)?.try_into().expect("length is checked"))
+    }
+
+    /// takes the bytes of the line without the newline
+    fn take_line_bytes(&mut self) -> Result<&'a [u8], Error> {
+        let end = self
+            .data
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .ok_or_else(|| pickle_error("line is not terminated"))?;
+        let line = self.take(end + 1)?;
+        Ok(&line[..end])
+    }
+
+    /// takes the line without the newline
+    fn take_line(&mut self) -> Result<&'a str, Error> {
+        std::str::from_utf8(self.take_line_bytes()?).map_err(pickle_error)
+    }
+
+    /// takes the length encoded in `N` little endian bytes and then the bytes of that length
+    fn take_sized<const N: usize>(&mut self) -> Result<&'a [u8], Error> {
+        let mut len = [0; 8];
+        len[..N].copy_from_slice(&self.take_array::<N>()?);
+        let len = usize::try_from(u64::from_le_bytes(len)).map_err(pickle_error)?;
+        self.take(len)
+    }
+
+    /// pops the object from the stack
+    fn pop(&mut self) -> Result<Shared, Error> {
+        match self.stack.pop() {
+            Some(Item::Object(object)) => Ok(object),
+            Some(Item::Mark) => Err(pickle_error("unexpected mark")),
+            None => Err(pickle_error("stack is empty")),
+        }
+    }
+
+    /// pops the objects till the last mark
+    fn pop_mark(&mut self) -> Result<Vec<Shared>, Error> {
+        let mark = self
+            .stack
+            .iter()
+            .rposition(|item| matches!(item, Item::Mark))
+            .ok_or_else(|| pickle_error("mark not found"))?;
+        self.stack
+            .drain(mark..)
+            .skip(1)
+            .map(|item| match item {
+                Item::Object(object) => Ok(object),
+                Item::Mark => Err(pickle_error("unexpected mark")),
+            })
+            .collect()
+    }
+
+    /// returns the object on top of the stack
+    fn top(&self) -> Result<&Shared, Error> {
+        match self.stack.last() {
+            Some(Item::Object(object)) => Ok(object),
+            _ => Err(pickle_error("no object on the stack")),
+        }
+    }
+
+    /// pushes the object to the stack
+    fn push(&mut self, object: Shared) {
+        self.stack.push(Item::Object(object));
+    }
+
+    /// pushes the value to the stack as new object
+    fn push_value(&mut self, value: Value) {
+        self.push(scalar(value));
+    }
+
+    /// memoizes the object on top of the stack
+    fn memoize(&mut self, idx: u64) -> Result<(), Error> {
+        let object = Rc::clone(self.top()?);
+        self.memo.insert(idx, object);
+        Ok(())
+    }
+
+    /// pushes the memoized object to the stack
+    fn get(&mut self, idx: u64) -> Result<(), Error> {
+        let object = self
+            .memo
+            .get(&idx)
+            .cloned()
+            .ok_or_else(|| pickle_error(format!("memo {idx} not found")))?;
+        self.push(object);
+        Ok(())
+    }
+
+    /// adds the items to the list or set on top of the stack
+    fn extend_list(&mut self, items: Vec<Shared>) -> Result<(), Error> {
+        match &mut *self.top()?.borrow_mut() {
+            Object::List(list) => {
+                list.extend(items);
+                Ok(())
+            }
+            object => Err(pickle_error(format!("can't append to {object:?}"))),
+        }
+    }
+
+    /// adds the key value pairs to the dict on top of the stack
+    fn extend_dict(&mut self, items: Vec<Shared>) -> Result<(), Error> {
+        let mut items = items.into_iter();
+        let pairs: Vec<_> = std::iter::from_fn(|| Some((items.next()?, items.next()?))).collect();
+        if items.next().is_some() {
+            return Err(pickle_error("odd number of dict items"));
+        }
+        match &mut *self.top()?.borrow_mut() {
+            Object::Dict(dict) => {
+                dict.extend(pairs);
+                Ok(())
+            }
+            object => Err(pickle_error(format!("can't set items of {object:?}"))),
+        }
+    }
+
+    /// calls the class or function with the args, only the functions used to pickle builtin types
+    /// are supported, everything else is created as an empty object
+    fn call(callable: &Shared, args: &Shared) -> Shared {
+        let callable = callable.borrow();
+        let args = args.borrow();
+        let (Object::Scalar(Value::Global(module, name)), Object::Tuple(args)) =
+            (&*callable, &*args)
+        else {
+            trace!("creating object of {callable:?}");
+            return shared(Object::Dict(Vec::new()));
+        };
+        match (module.as_str(), name.as_str()) {
+            // bytes pickled with protocol < 3
+            ("_codecs", "encode") => {
+                let bytes = match args.first().map(|arg| arg.borrow()).as_deref() {
+                    Some(Object::Scalar(Value::Str(value))) => {
+                        value.chars().map(|c| c as u32 as u8).collect()
+                    }
+                    _ => Vec::new(),
+                };
+                scalar(Value::Bytes(bytes))
+            }
+            // objects of dict subclasses pickled with protocol < 2, items are in the args
+            ("copy_reg" | "copyreg", "_reconstructor") => {
+                let items = match args.get(2).map(|arg| arg.borrow()).as_deref() {
+                    Some(Object::Dict(items)) => items.clone(),
+                    _ => Vec::new(),
+                };
+                shared(Object::Dict(items))
+            }
+            _ => {
+                trace!("creating object of {module}.{name}");
+                shared(Object::Dict(Vec::new()))
+            }
+        }
+    }
+
+    /// executes the opcodes till `STOP` and returns the value on top of the stack
+    fn run(&mut self) -> Result<Shared, Error> {
+        loop {
+            let [opcode] = self.take_array::<1>()?;
+            match opcode {
+                // PROTO
+                0x80 => {
+                    self.take(1)?;
+                }
+                // FRAME
+                0x95 => {
+                    self.take(8)?;
+                }
+                // STOP
+                b'.' => return self.pop(),
+                // MARK
+                b'(' => self.stack.push(Item::Mark),
+                // POP
+                b'0' => {
+                    self.stack.pop();
+                }
+                // POP_MARK
+                b'1' => {
+                    self.pop_mark()?;
+                }
+                // DUP
+                b'2' => {
+                    let object = Rc::clone(self.top()?);
+                    self.push(object);
+                }
+                b'N' => self.push_value(Value::None),
+                // NEWTRUE, NEWFALSE
+                0x88 => self.push_value(Value::Bool(true)),
+                0x89 => self.push_value(Value::Bool(false)),
+                // INT, protocol 0 writes bools as 00 and 01
+                b'I' => {
+                    let value = match self.take_line()? {
+                        "00" => Value::Bool(false),
+                        "01" => Value::Bool(true),
+                        line => Value::Int(line.parse().map_err(pickle_error)?),
+                    };
+                    self.push_value(value);
+                }
+                // LONG
+                b'L' => {
+                    let line = self.take_line()?;
+                    let value = line.trim_end_matches('L').parse().map_err(pickle_error)?;
+                    self.push_value(Value::Int(value));
+                }
+                // BININT
+                b'J' => {
+                    let value = i32::from_le_bytes(self.take_array()?);
+                    self.push_value(Value::Int(value.into()));
+                }
+                // BININT1
+                b'K' => {
+                    let [value] = self.take_array::<1>()?;
+                    self.push_value(Value::Int(value.into()));
+                }
+                // BININT2
+                b'M' => {
+                    let value = u16::from_le_bytes(self.take_array()?);
+                    self.push_value(Value::Int(value.into()));
+                }
+                // LONG1, LONG4
+                0x8a | 0x8b => {
+                    let bytes = if opcode == 0x8a {
+                        self.take_sized::<1>()?
+                    } else {
+                        self.take_sized::<4>()?
+                    };
+                    if bytes.len() > 8 {
+                        return Err(pickle_error("integer is too large"));
+                    }
+                    // sign extend the two's complement little endian bytes
+                    let fill = if bytes.last().is_some_and(|byte| byte & 0x80 != 0) {
+                        0xff
+                    } else {
+                        0
+                    };
+                    let mut value = [fill; 8];
+                    value[..bytes.len()].copy_from_slice(bytes);
+                    self.push_value(Value::Int(i64::from_le_bytes(value)));
+                }
+                // FLOAT
+                b'F' => {
+                    let value = self.take_line()?.parse().map_err(pickle_error)?;
+                    self.push_value(Value::Float(value));
+                }
+                // BINFLOAT
+                b'G' => {
+                    let value = f64::from_be_bytes(self.take_array()?);
+                    self.push_value(Value::Float(value));
+                }
+                // STRING, python2 str in quotes
+                b'S' => {
+                    let value = unescape_string(self.take_line()?)?;
+                    self.push_value(Value::Bytes(value));
+                }
+                // BINSTRING, SHORT_BINSTRING, BINBYTES, SHORT_BINBYTES, BINBYTES8
+                b'T' | b'B' => {
+                    let value = self.take_sized::<4>()?.to_vec();
+                    self.push_value(Value::Bytes(value));
+                }
+                b'U' | b'C' => {
+                    let value = self.take_sized::<1>()?.to_vec();
+                    self.push_value(Value::Bytes(value));
+                }
+                0x8e => {
+                    let value = self.take_sized::<8>()?.to_vec();
+                    self.push_value(Value::Bytes(value));
+                }
+                // UNICODE
+                b'V' => {
+                    // characters below 256 are written as latin-1 bytes
+                    let line: String = self
+                        .take_line_bytes()?
+                        .iter()
+                        .copied()
+                        .map(char::from)
+                        .collect();
+                    let value = unescape_unicode(&line);
+                    self.push_value(Value::Str(value));
+                }
+                // BINUNICODE, SHORT_BINUNICODE, BINUNICODE8
+                b'X' | 0x8c | 0x8d => {
+                    let bytes = match opcode {
+                        b'X' => self.take_sized::<4>()?,
+                        0x8c => self.take_sized::<1>()?,
+                        _ => self.take_sized::<8>()?,
+                    };
+                    self.push_value(Value::Str(String::from_utf8_lossy(bytes).into_owned()));
+                }
+                // EMPTY_LIST, EMPTY_SET
+                b']' | 0x8f => self.push(shared(Object::List(Vec::new()))),
+                // LIST, FROZENSET
+                b'l' | 0x91 => {
+                    let items = self.pop_mark()?;
+                    self.push(shared(Object::List(items)));
+                }
+                // APPEND
+                b'a' => {
+                    let value = self.pop()?;
+                    self.extend_list(vec![value])?;
+                }
+                // APPENDS, ADDITEMS
+                b'e' | 0x90 => {
+                    let items = self.pop_mark()?;
+                    self.extend_list(items)?;
+                }
+                // EMPTY_TUPLE
+                b')' => self.push(shared(Object::Tuple(Vec::new()))),
+                // TUPLE
+                b't' => {
+                    let items = self.pop_mark()?;
+                    self.push(shared(Object::Tuple(items)));
+                }
+                // TUPLE1, TUPLE2, TUPLE3
+                0x85..=0x87 => {
+                    let len = usize::from(opcode - 0x84);
+                    if self.stack.len() < len {
+                        return Err(pickle_error("not enough items for tuple"));
+                    }
+                    let mut items = Vec::with_capacity(len);
+                    for _ in 0..len {
+                        items.push(self.pop()?);
+                    }
+                    items.reverse();
+                    self.push(shared(Object::Tuple(items)));
+                }
+                // EMPTY_DICT
+                b'}' => self.push(shared(Object::Dict(Vec::new()))),
+                // DICT
+                b'd' => {
+                    let items = self.pop_mark()?;
+                    self.push(shared(Object::Dict(Vec::new())));
+                    self.extend_dict(items)?;
+                }
+                // SETITEM
+                b's' => {
+                    let value = self.pop()?;
+                    let key = self.pop()?;
+                    self.extend_dict(vec![key, value])?;
+                }
+                // SETITEMS
+                b'u' => {
+                    let items = self.pop_mark()?;
+                    self.extend_dict(items)?;
+                }
+                // GLOBAL
+                b'c' => {
+                    let module = self.take_line()?.to_string();
+                    let name = self.take_line()?.to_string();
+                    self.push_value(Value::Global(module, name));
+                }
+                // STACK_GLOBAL
+                0x93 => {
+                    let name = self.pop()?;
+                    let module = self.pop()?;
+                    let global = match (&*module.borrow(), &*name.borrow()) {
+                        (Object::Scalar(Value::Str(module)), Object::Scalar(Value::Str(name))) => {
+                            Value::Global(module.clone(), name.clone())
+                        }
+                        _ => return Err(pickle_error("invalid global")),
+                    };
+                    self.push_value(global);
+                }
+                // REDUCE
+                b'R' => {
+                    let args = self.pop()?;
+                    let callable = self.pop()?;
+                    self.push(Self::call(&callable, &args));
+                }
+                // NEWOBJ
+                0x81 => {
+                    let args = self.pop()?;
+                    let class = self.pop()?;
+                    self.push(Self::call(&class, &args));
+                }
+                // NEWOBJ_EX
+                0x92 => {
+                    let _kwargs = self.pop()?;
+                    let args = self.pop()?;
+                    let class = self.pop()?;
+                    self.push(Self::call(&class, &args));
+                }
+                // OBJ
+                b'o' => {
+                    let mut items = self.pop_mark()?.into_iter();
+                    let class = items
+                        .next()
+                        .ok_or_else(|| pickle_error("class of object not found"))?;
+                    let args = shared(Object::Tuple(items.collect()));
+                    self.push(Self::call(&class, &args));
+                }
+                // INST
+                b'i' => {
+                    let module = self.take_line()?.to_string();
+                    let name = self.take_line()?.to_string();
+                    let args = shared(Object::Tuple(self.pop_mark()?));
+                    let class = scalar(Value::Global(module, name));
+                    self.push(Self::call(&class, &args));
+                }
+                // BUILD, attributes of the object are added to its items
+                b'b' => {
+                    let mut state = self.pop()?;
+                    // state with slots
+                    let dict_state = match &*state.borrow() {
+                        Object::Tuple(state) => state.first().cloned(),
+                        _ => None,
+                    };
+                    if let Some(dict_state) = dict_state {
+                        state = dict_state;
+                    }
+                    let attributes = match &*state.borrow() {
+                        Object::Dict(attributes) => attributes.clone(),
+                        _ => Vec::new(),
+                    };
+                    if let Object::Dict(object) = &mut *self.top()?.borrow_mut() {
+                        object.extend(attributes);
+                    }
+                }
+                // PUT
+                b'p' => {
+                    let idx = self.take_line()?.parse().map_err(pickle_error)?;
+                    self.memoize(idx)?;
+                }
+                // BINPUT
+                b'q' => {
+                    let [idx] = self.take_array::<1>()?;
+                    self.memoize(idx.into())?;
+                }
+                // LONG_BINPUT
+                b'r' => {
+                    let idx = u32::from_le_bytes(self.take_array()?);
+                    self.memoize(idx.into())?;
+                }
+                // MEMOIZE
+                0x94 => {
+                    let idx = self.memo.len() as u64;
+                    self.memoize(idx)?;
+                }
+                // GET
+                b'g' => {
+                    let idx = self.take_line()?.parse().map_err(pickle_error)?;
+                    self.get(idx)?;
+                }
+                // BINGET
+                b'h' => {
+                    let [idx] = self.take_array::<1>()?;
+                    self.get(idx.into())?;
+                }
+                // LONG_BINGET
+                b'j' => {
+                    let idx = u32::from_le_bytes(self.take_array()?);
+                    self.get(idx.into())?;
+                }
+                opcode => return Err(pickle_error(format!("unsupported opcode {opcode:#x}"))),
+            }
+        }
+    }
+}
+
+/// reads the value from the pickle
+pub fn from_slice(data: &[u8]) -> Result<Value, Error> {
+    let object = Machine {
+        data,
+        stack: Vec::new(),
+        memo: HashMap::new(),
+    }
+    .run()?;
+    Ok(to_value(&object, &mut Vec::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `data` of the tests pickled by python 3.11, `a` and `b` are the same list
+    fn expected() -> Value {
+        let str = |value: &str| Value::Str(value.to_string());
+        let shared = Value::List(vec![Value::Int(1), Value::Int(2)]);
+        Value::Dict(vec![
+            (str("name"), str("caf\u{e9}")),
+            (str("count"), Value::Int(-3)),
+            (str("big"), Value::Int(1 << 40)),
+            (str("ratio"), Value::Float(0.25)),
+            (str("flag"), Value::Bool(true)),
+            (str("none"), Value::None),
+            (str("raw"), Value::Bytes(vec![0xff, 0])),
+            (str("tuple"), Value::Tuple(vec![Value::Int(1), str("a")])),
+            (str("a"), shared.clone()),
+            (str("b"), shared),
+        ])
+    }
+
+    #[test]
+    fn protocol_0() {
+        let data = b"(dp0\x0aVname\x0ap1\x0aVcaf\xe9\x0ap2\x0asVcount\x0ap3\x0aI-3\x0asVbig\x0ap4\x0aL1099511627776L\x0asVratio\x0ap5\x0aF0.25\x0asVflag\x0ap6\x0aI01\x0asVnone\x0ap7\x0aNsVraw\x0ap8\x0ac_codecs\x0aencode\x0ap9\x0a(V\xff\x5cu0000\x0ap10\x0aVlatin1\x0ap11\x0atp12\x0aRp13\x0asVtuple\x0ap14\x0a(I1\x0aVa\x0ap15\x0atp16\x0asg15\x0a(lp17\x0aI1\x0aaI2\x0aasVb\x0ap18\x0ag17\x0as.";
+        assert_eq!(from_slice(data).unwrap(), expected());
+    }
+
+    #[test]
+    fn protocol_2() {
+        let data = b"\x80\x02}q\x00(X\x04\x00\x00\x00nameq\x01X\x05\x00\x00\x00caf\xc3\xa9q\x02X\x05\x00\x00\x00countq\x03J\xfd\xff\xff\xffX\x03\x00\x00\x00bigq\x04\x8a\x06\x00\x00\x00\x00\x00\x01X\x05\x00\x00\x00ratioq\x05G?\xd0\x00\x00\x00\x00\x00\x00X\x04\x00\x00\x00flagq\x06\x88X\x04\x00\x00\x00noneq\x07NX\x03\x00\x00\x00rawq\x08c_codecs\x0aencode\x0aq\x09X\x03\x00\x00\x00\xc3\xbf\x00q\x0aX\x06\x00\x00\x00latin1q\x0b\x86q\x0cRq\x0dX\x05\x00\x00\x00tupleq\x0eK\x01X\x01\x00\x00\x00aq\x0f\x86q\x10h\x0f]q\x11(K\x01K\x02eX\x01\x00\x00\x00bq\x12h\x11u.";
+        assert_eq!(from_slice(data).unwrap(), expected());
+    }
+
+    #[test]
+    fn protocol_4() {
+        let data = b"\x80\x04\x95{\x00\x00\x00\x00\x00\x00\x00}\x94(\x8c\x04name\x94\x8c\x05caf\xc3\xa9\x94\x8c\x05count\x94J\xfd\xff\xff\xff\x8c\x03big\x94\x8a\x06\x00\x00\x00\x00\x00\x01\x8c\x05ratio\x94G?\xd0\x00\x00\x00\x00\x00\x00\x8c\x04flag\x94\x88\x8c\x04none\x94N\x8c\x03raw\x94C\x02\xff\x00\x94\x8c\x05tuple\x94K\x01\x8c\x01a\x94\x86\x94h\x0b]\x94(K\x01K\x02e\x8c\x01b\x94h\x0du.";
+        assert_eq!(from_slice(data).unwrap(), expected());
+    }
+
+    #[test]
+    fn python2_strings() {
+        // python 2 pickle of a dict subclass with `str` and `unicode` values
+        let data = b"(lp0\x0accopy_reg\x0a_reconstructor\x0ap1\x0a(cquodlibet.formats.mp3\x0aMP3File\x0ap2\x0ac__builtin__\x0adict\x0ap3\x0a(dp4\x0aS'~filename'\x0ap5\x0aS'/music/caf\x5cxc3\x5cxa9.mp3'\x0ap6\x0asS'~#playcount'\x0ap7\x0aI3\x0asS'title'\x0ap8\x0aVcaf\xe9\x0ap9\x0asS'quote'\x0ap10\x0aS'it\x5c's \x22a\x22\x5c\x5c\x5cn\x5ct\x5c001'\x0ap11\x0astp12\x0aRp13\x0aa.";
+        let Value::List(songs) = from_slice(data).unwrap() else {
+            panic!("expected list of songs");
+        };
+        assert_eq!(
+            songs[0].get("~filename"),
+            Some(&Value::Bytes(b"/music/caf\xc3\xa9.mp3".to_vec()))
+        );
+        assert_eq!(songs[0].get("~#playcount"), Some(&Value::Int(3)));
+        assert_eq!(
+            songs[0].get("title"),
+            Some(&Value::Str("caf\u{e9}".to_string()))
+        );
+        assert_eq!(
+            songs[0].get("quote"),
+            Some(&Value::Bytes(b"it's \"a\"\\\n\t\x01".to_vec()))
+        );
+    }
+
+    #[test]
+    fn memoized_containers_are_shared() {
+        // l = []; d = {"a": l}; l.append(1) is pickled as an empty list which is appended after
+        // it is memoized and referenced again
+        let data = b"\x80\x02}q\x00X\x01\x00\x00\x00aq\x01]q\x02sh\x02K\x01a0h\x00.";
+        assert_eq!(
+            from_slice(data).unwrap(),
+            Value::Dict(vec![(
+                Value::Str("a".to_string()),
+                Value::List(vec![Value::Int(1)])
+            )])
+        );
+    }
+
+    #[test]
+    fn recursive_containers() {
+        // l = []; l.append(l)
+        let data = b"\x80\x02]q\x00h\x00a.";
+        assert_eq!(from_slice(data).unwrap(), Value::List(vec![Value::None]));
+    }
+
+    #[test]
+    fn invalid_pickles() {
+        for data in [
+            &b""[..],
+            b"\x80\x02]q\x00",
+            b"\x80\x02h\x05.",
+            b"S'unterminated\n.",
+            b"S'bad \\x4'\n.",
+            b"\xff.",
+        ] {
+            assert!(from_slice(data).is_err(), "{data:?}");
+        }
+    }
+}
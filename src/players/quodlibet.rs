@@ -0,0 +1,109 @@
+//! reads Quod Libet's library, `~/.config/quodlibet/songs` or `~/.quodlibet/songs`. It is a
+//! pickled list of songs, each song is a dict of its tags and internal `~` keys
+use super::{pickle, PlayerSong};
+use crate::{error::Error, stats::Statistics};
+use chrono::DateTime;
+use log::{debug, error, warn};
+use std::{ffi::OsString, os::unix::ffi::OsStringExt, path::Path, path::PathBuf};
+
+/// returns the first value of the tag, multiple values are separated by newline
+fn tag(song: &pickle::Value, name: &str) -> Option<String> {
+    let value = song.get(name)?.as_str()?;
+    value.lines().next().map(str::to_owned)
+}
+
+/// converts the song of the library, songs without filename are ignored
+fn to_player_song(song: &pickle::Value) -> Option<PlayerSong> {
+    let path = match song.get("~filename")? {
+        // filenames are bytes in python2
+        pickle::Value::Bytes(path) => PathBuf::from(OsString::from_vec(path.clone())),
+        path => PathBuf::from(path.as_str()?.into_owned()),
+    };
+    let number = |key| song.get(key).and_then(pickle::Value::as_f64);
+    let count = |key| number(key).map_or(0, |count| count.max(0.0) as u32);
+    let stats = Statistics {
+        play_cnt: count("~#playcount"),
+        skip_cnt: count("~#skipcount"),
+        last_played: number("~#lastplayed")
+            .filter(|secs| *secs > 0.0)
+            .and_then(|secs| DateTime::from_timestamp(secs as i64, 0)),
+        // rating is in range 0-1, it is stored only if the song is rated
+        user_rating: number("~#rating").map(|rating| rating as f32 * 10.0),
+        ..Default::default()
+    };
+    Some(PlayerSong {
+        path,
+        title: tag(song, "title"),
+        artist: tag(song, "artist"),
+        album: tag(song, "album"),
+        stats,
+    })
+}
+
+/// reads all the songs from the pickled library
+pub fn read_library(library: &Path) -> Result<Vec<PlayerSong>, Error> {
+    let data = std::fs::read(library).map_err(|err| {
+        error!("couldn't read {:?} due to {err}", library);
+        Error::Import
+    })?;
+    let songs = match pickle::from_slice(&data)? {
+        pickle::Value::List(songs) => songs,
+        value => {
+            error!("expected list of songs in the library, found {:?}", value);
+            return Err(Error::Import);
+        }
+    };
+    debug!("found {} songs in quodlibet library", songs.len());
+    Ok(songs
+        .iter()
+        .filter_map(|song| {
+            let player_song = to_player_song(song);
+            if player_song.is_none() {
+                warn!("skipping song without filename in quodlibet library");
+            }
+            player_song
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn songs_of_library() {
+        // library of two `MP3File`s pickled with protocol 2 like Quod Libet does
+        let data = b"\x80\x02]q\x00(cquodlibet.formats.mp3\x0aMP3File\x0aq\x01)\x81q\x02(X\x09\x00\x00\x00~filenameq\x03X\x17\x00\x00\x00/music/Artist/caf\xc3\xa9.mp3q\x04X\x05\x00\x00\x00titleq\x05X\x05\x00\x00\x00Caf\xc3\xa9q\x06X\x06\x00\x00\x00artistq\x07X\x0c\x00\x00\x00Artist\x0aOtherq\x08X\x05\x00\x00\x00albumq\x09X\x05\x00\x00\x00Albumq\x0aX\x0b\x00\x00\x00~#playcountq\x0bK\x0cX\x0b\x00\x00\x00~#skipcountq\x0cK\x02X\x0c\x00\x00\x00~#lastplayedq\x0dJ\x00\xf1SeX\x08\x00\x00\x00~#ratingq\x0eG?\xe8\x00\x00\x00\x00\x00\x00uh\x01)\x81q\x0f(h\x03X\x10\x00\x00\x00/music/other.mp3q\x10h\x05X\x05\x00\x00\x00Otherq\x11h\x0bK\x00ue.";
+        let pickle::Value::List(songs) = pickle::from_slice(data).unwrap() else {
+            panic!("expected list of songs");
+        };
+        let songs: Vec<_> = songs.iter().filter_map(to_player_song).collect();
+        assert_eq!(songs.len(), 2);
+        let song = &songs[0];
+        assert_eq!(song.path, PathBuf::from("/music/Artist/caf\u{e9}.mp3"));
+        assert_eq!(song.title.as_deref(), Some("Caf\u{e9}"));
+        assert_eq!(song.artist.as_deref(), Some("Artist"));
+        assert_eq!(song.album.as_deref(), Some("Album"));
+        assert_eq!(song.stats.play_cnt, 12);
+        assert_eq!(song.stats.skip_cnt, 2);
+        assert_eq!(song.stats.last_played.unwrap().timestamp(), 1700000000);
+        assert_eq!(song.stats.user_rating, Some(7.5));
+        assert_eq!(songs[1].path, PathBuf::from("/music/other.mp3"));
+        assert_eq!(songs[1].stats.play_cnt, 0);
+        assert_eq!(songs[1].stats.user_rating, None);
+    }
+
+    #[test]
+    fn python2_filename_bytes() {
+        let data = b"(lp0\x0accopy_reg\x0a_reconstructor\x0ap1\x0a(cquodlibet.formats.mp3\x0aMP3File\x0ap2\x0ac__builtin__\x0adict\x0ap3\x0a(dp4\x0aS'~filename'\x0ap5\x0aS'/music/caf\x5cxe9.mp3'\x0ap6\x0asS'~#playcount'\x0ap7\x0aI3\x0astp8\x0aRp9\x0aa.";
+        let pickle::Value::List(songs) = pickle::from_slice(data).unwrap() else {
+            panic!("expected list of songs");
+        };
+        let song = to_player_song(&songs[0]).unwrap();
+        assert_eq!(
+            song.path,
+            PathBuf::from(OsString::from_vec(b"/music/caf\xe9.mp3".to_vec()))
+        );
+        assert_eq!(song.stats.play_cnt, 3);
+    }
+}
@@ -0,0 +1,83 @@
+//! reads Rhythmbox's library, `~/.local/share/rhythmbox/rhythmdb.xml`.
+//! Rhythmbox doesn't count skips, only play count, last played time, first seen time and rating
+//! in 0-5 stars are imported
+use super::{uri_to_path, PlayerSong};
+use crate::{error::Error, stats::Statistics};
+use chrono::DateTime;
+use log::{debug, error, trace};
+use quick_xml::{events::Event, Reader};
+use std::path::Path;
+
+/// logs the xml error and converts it to crate error
+fn xml_error(err: quick_xml::Error) -> Error {
+    error!("invalid rhythmdb: {err}");
+    Error::Import
+}
+
+/// sets the field of the song from the value of the xml element
+fn set_field(song: &mut PlayerSong, field: &[u8], value: String) {
+    let time = || {
+        value
+            .parse()
+            .ok()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+    };
+    match field {
+        b"location" => song.path = uri_to_path(&value),
+        b"title" => song.title = Some(value),
+        b"artist" => song.artist = Some(value),
+        b"album" => song.album = Some(value),
+        b"play-count" => song.stats.play_cnt = value.parse().unwrap_or_default(),
+        b"last-played" => song.stats.last_played = time(),
+        b"first-seen" => song.stats.first_seen = time(),
+        // stars are converted to 0-10
+        b"rating" => song.stats.user_rating = value.parse::<f32>().ok().map(|stars| stars * 2.0),
+        _ => trace!("ignoring field {}", String::from_utf8_lossy(field)),
+    }
+}
+
+/// reads all the songs from rhythmdb
+pub fn read_library(library: &Path) -> Result<Vec<PlayerSong>, Error> {
+    let mut reader = Reader::from_file(library).map_err(xml_error)?;
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut songs = Vec::new();
+    // song of the current entry, entries other than songs(radio, podcasts) are ignored
+    let mut song: Option<PlayerSong> = None;
+    let mut field = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf).map_err(xml_error)? {
+            Event::Start(element) if element.name().as_ref() == b"entry" => {
+                let is_song = element
+                    .try_get_attribute("type")
+                    .map_err(xml_error)?
+                    .is_some_and(|kind| kind.value.as_ref() == b"song");
+                song = is_song.then(|| PlayerSong {
+                    path: Default::default(),
+                    title: None,
+                    artist: None,
+                    album: None,
+                    stats: Statistics::default(),
+                });
+            }
+            Event::Start(element) => field = element.name().as_ref().to_vec(),
+            Event::Text(text) => {
+                if let Some(song) = song.as_mut() {
+                    let value = text.unescape().map_err(xml_error)?.into_owned();
+                    set_field(song, &field, value);
+                }
+            }
+            Event::End(element) if element.name().as_ref() == b"entry" => {
+                if let Some(song) = song.take() {
+                    debug!("found {:?} in rhythmdb", song.path);
+                    songs.push(song);
+                }
+            }
+            Event::End(_) => field.clear(),
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(songs)
+}
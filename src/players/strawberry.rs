@@ -0,0 +1,82 @@
+//! reads Strawberry's library, `~/.local/share/strawberry/strawberry/strawberry.db`. Clementine's
+//! database has the same `songs` table, except that location of the song is in `filename` column
+use super::{uri_to_path, PlayerSong};
+use crate::{error::Error, stats::Statistics};
+use chrono::DateTime;
+use log::{debug, error};
+use rusqlite::{Connection, OpenFlags};
+use std::path::Path;
+
+/// logs the database error and converts it to crate error
+fn db_error(err: rusqlite::Error) -> Error {
+    error!("couldn't read the library: {err}");
+    Error::Import
+}
+
+/// reads all the songs from the database
+pub fn read_library(library: &Path) -> Result<Vec<PlayerSong>, Error> {
+    let conn =
+        Connection::open_with_flags(library, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(db_error)?;
+    let columns: Vec<String> = conn
+        .prepare("SELECT name FROM pragma_table_info('songs')")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<Result<_, _>>()
+        })
+        .map_err(db_error)?;
+    let location = if columns.iter().any(|column| column == "url") {
+        "url"
+    } else {
+        "filename"
+    };
+    debug!("reading location of the songs from {location} column");
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {location}, title, artist, album, playcount, skipcount, lastplayed, rating \
+            FROM songs"
+        ))
+        .map_err(db_error)?;
+    let songs = stmt
+        .query_map([], |row| {
+            // location is stored as blob in Clementine
+            let location = match row.get_ref(0)? {
+                rusqlite::types::ValueRef::Text(text) | rusqlite::types::ValueRef::Blob(text) => {
+                    String::from_utf8_lossy(text).into_owned()
+                }
+                _ => String::new(),
+            };
+            let rating: Option<f64> = row.get(7)?;
+            let last_played: Option<i64> = row.get(6)?;
+            // counts are stored as signed integers
+            let count = |idx: usize| -> rusqlite::Result<u32> {
+                let count: Option<i64> = row.get(idx)?;
+                Ok(count
+                    .and_then(|count| count.try_into().ok())
+                    .unwrap_or_default())
+            };
+            let stats = Statistics {
+                play_cnt: count(4)?,
+                skip_cnt: count(5)?,
+                // never played songs have -1
+                last_played: last_played
+                    .filter(|secs| *secs > 0)
+                    .and_then(|secs| DateTime::from_timestamp(secs, 0)),
+                // rating is in range 0-1, unrated songs have -1
+                user_rating: rating
+                    .filter(|rating| *rating >= 0.0)
+                    .map(|rating| rating as f32 * 10.0),
+                ..Default::default()
+            };
+            Ok(PlayerSong {
+                path: uri_to_path(&location),
+                title: row.get(1)?,
+                artist: row.get(2)?,
+                album: row.get(3)?,
+                stats,
+            })
+        })
+        .map_err(db_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(db_error)?;
+    Ok(songs)
+}
@@ -0,0 +1,112 @@
+//! generates playlists from the songs selected based on their stats
+use crate::{
+    error::CustomEror,
+    stats::{RatingConfig, Statistics, StatsFilter},
+    store::StatsStore,
+    ConnType,
+};
+use clap::Args;
+use log::{debug, error, info, warn};
+use std::{collections::HashMap, path::PathBuf, process::exit};
+
+/// Configuration Options for MakePlaylist
+#[derive(Args, Debug)]
+pub struct PlaylistConfig {
+    /// filters to select the songs
+    #[command(flatten)]
+    filter: StatsFilter,
+    /// options to compute the rating
+    #[command(flatten)]
+    rating: RatingConfig,
+    /// maximum number of songs in the playlist, highest rated songs are picked
+    #[arg(short, long)]
+    limit: Option<usize>,
+    /// save the songs to mpd's stored playlist with this name
+    #[arg(short = 'P', long, required_unless_present("queue"))]
+    playlist: Option<String>,
+    /// load the songs into the queue
+    #[arg(short = 'Q', long, conflicts_with("playlist"))]
+    queue: bool,
+    /// append the songs instead of replacing the playlist/queue
+    #[arg(short, long)]
+    append: bool,
+}
+
+/// returns all songs of mpd which satisfies the filters, sorted by rating with highest first
+pub fn select_songs(
+    client: &mut mpd::Client<ConnType>,
+    store: &mut dyn StatsStore,
+    filter: &StatsFilter,
+    rating: &RatingConfig,
+) -> Vec<(PathBuf, Statistics)> {
+    let mut all_stats: HashMap<PathBuf, Statistics> = store
+        .list(client)
+        .try_unwrap("Failed to get the stats")
+        .into_iter()
+        .collect();
+    // songs without stats are never played, so default stats are used for them
+    let mut songs: Vec<(PathBuf, Statistics)> = client
+        .listall()
+        .try_unwrap("Couldn't get the songs from mpd")
+        .into_iter()
+        .map(|song| {
+            let song_path = PathBuf::from(song.file);
+            let stats = all_stats.remove(&song_path).unwrap_or_default();
+            (song_path, stats)
+        })
+        .filter(|(_, stats)| filter.matches(stats, rating))
+        .collect();
+    songs.sort_by(|(_, s1), (_, s2)| s2.get_ratings(rating).total_cmp(&s1.get_ratings(rating)));
+    debug!("{} songs selected", songs.len());
+    songs
+}
+
+/// creates playlist from the songs selected by their stats, either saves it as mpd's stored
+/// playlist or loads it into the queue
+pub fn make_playlist(
+    client: &mut mpd::Client<ConnType>,
+    config: &PlaylistConfig,
+    store: &mut dyn StatsStore,
+) {
+    let mut songs = select_songs(client, store, &config.filter, &config.rating);
+    if let Some(limit) = config.limit {
+        songs.truncate(limit);
+    }
+    if songs.is_empty() {
+        warn!("no songs matches the filters");
+    }
+    if let Some(name) = &config.playlist {
+        if !config.append
+            && client
+                .playlists()
+                .try_unwrap("Couldn't get the playlists from mpd")
+                .iter()
+                .any(|playlist| &playlist.name == name)
+        {
+            client
+                .pl_clear(name)
+                .try_unwrap("Couldn't clear the playlist");
+        }
+        for (song, _) in &songs {
+            let song = song.to_string_lossy();
+            if let Err(err) = client.pl_push(name, song.as_ref()) {
+                error!("Couldn't add {song} to playlist {name} due to {err}");
+            }
+        }
+        info!("saved {} songs to playlist {name}", songs.len());
+    } else if config.queue {
+        if !config.append {
+            client.clear().try_unwrap("Couldn't clear the queue");
+        }
+        for (song, _) in &songs {
+            let song = song.to_string_lossy();
+            if let Err(err) = client.push(song.as_ref()) {
+                error!("Couldn't add {song} to queue due to {err}");
+            }
+        }
+        info!("loaded {} songs into queue", songs.len());
+    } else {
+        error!("either playlist or queue must be given");
+        exit(1);
+    }
+}
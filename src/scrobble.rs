@@ -0,0 +1,275 @@
+//! reads listens from ListenBrainz json exports and Last.fm scrobble csv exports, so that
+//! listening history of other services can be imported as play counts
+use crate::error::Error;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use clap::ValueEnum;
+use log::{debug, error, warn};
+use serde::Deserialize;
+use std::io::{BufRead, BufReader, Read};
+
+/// service from which the listens are exported
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ListenSource {
+    /// ListenBrainz export, either a json array or json lines of listens
+    #[value(name = "listenbrainz")]
+    ListenBrainz,
+    /// Last.fm scrobbles in csv, either `artist,album,title,date` without header or with a header
+    /// having `artist`, `album`, `track`/`title` and `uts`/`date` columns
+    #[value(name = "lastfm")]
+    Lastfm,
+}
+
+/// single listen of a song
+#[derive(Debug)]
+pub struct Listen {
+    /// artist of the song
+    pub artist: String,
+    /// title of the song
+    pub title: String,
+    /// album of the song if known
+    pub album: Option<String>,
+    /// time at which song is listened
+    pub time: Option<DateTime<Utc>>,
+}
+
+/// listen in ListenBrainz export
+#[derive(Debug, Deserialize)]
+struct BrainzListen {
+    /// unix time at which song is listened
+    listened_at: Option<i64>,
+    /// details of the song
+    track_metadata: BrainzTrack,
+}
+
+/// details of the song in ListenBrainz export
+#[derive(Debug, Deserialize)]
+struct BrainzTrack {
+    /// artist of the song
+    artist_name: String,
+    /// title of the song
+    track_name: String,
+    /// album of the song
+    release_name: Option<String>,
+}
+
+impl From<BrainzListen> for Listen {
+    fn from(listen: BrainzListen) -> Self {
+        Self {
+            artist: listen.track_metadata.artist_name,
+            title: listen.track_metadata.track_name,
+            album: listen
+                .track_metadata
+                .release_name
+                .filter(|album| !album.is_empty()),
+            time: listen
+                .listened_at
+                .and_then(|secs| DateTime::from_timestamp(secs, 0)),
+        }
+    }
+}
+
+/// reads listens of the source from the reader
+pub fn read_listens(source: ListenSource, reader: impl Read) -> Result<Vec<Listen>, Error> {
+    match source {
+        ListenSource::ListenBrainz => read_listenbrainz(reader),
+        ListenSource::Lastfm => read_lastfm(reader),
+    }
+}
+
+/// reads ListenBrainz export, old exports are a single json array and new exports have a listen
+/// per line
+fn read_listenbrainz(reader: impl Read) -> Result<Vec<Listen>, Error> {
+    let mut reader = BufReader::new(reader);
+    let is_array = reader
+        .fill_buf()
+        .map_err(|err| {
+            error!("couldn't read the listens due to {err}");
+            Error::Import
+        })?
+        .iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        == Some(&b'[');
+    if is_array {
+        let listens: Vec<BrainzListen> = serde_json::from_reader(reader).map_err(|err| {
+            error!("invalid ListenBrainz export: {err}");
+            Error::Import
+        })?;
+        return Ok(listens.into_iter().map(Listen::from).collect());
+    }
+    let mut listens = Vec::new();
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line.map_err(|err| {
+            error!("couldn't read the listens due to {err}");
+            Error::Import
+        })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<BrainzListen>(&line) {
+            Ok(listen) => listens.push(listen.into()),
+            Err(err) => warn!("skipping invalid listen at line {}: {err}", line_no + 1),
+        }
+    }
+    Ok(listens)
+}
+
+/// parses the time of Last.fm export, it is either unix time or `31 Jan 2021 12:34` in utc
+fn parse_lastfm_time(time: &str) -> Option<DateTime<Utc>> {
+    if let Ok(secs) = time.parse() {
+        return DateTime::from_timestamp(secs, 0);
+    }
+    NaiveDateTime::parse_from_str(time, "%d %b %Y %H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(time, "%d %b %Y, %H:%M"))
+        .map(|time| time.and_utc())
+        .ok()
+}
+
+/// reads Last.fm scrobbles csv
+fn read_lastfm(reader: impl Read) -> Result<Vec<Listen>, Error> {
+    let mut records = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader)
+        .into_records();
+    let first = match records.next() {
+        Some(record) => record.map_err(|err| {
+            error!("invalid Last.fm export: {err}");
+            Error::Import
+        })?,
+        None => return Ok(Vec::new()),
+    };
+    let column = |names: &[&str]| {
+        first
+            .iter()
+            .position(|field| names.iter().any(|name| field.eq_ignore_ascii_case(name)))
+    };
+    // columns of artist, album, title and time
+    let header = column(&["artist"]).zip(column(&["track", "title"]));
+    let (columns, first) = match header {
+        Some((artist, title)) => {
+            debug!("Last.fm export has a header {:?}", first);
+            let time = column(&["uts"]).or_else(|| column(&["utc_time", "date", "time"]));
+            ((artist, column(&["album"]), title, time), None)
+        }
+        None => ((0, Some(1), 2, Some(3)), Some(first)),
+    };
+    let (artist, album, title, time) = columns;
+    // line numbers start after the header
+    let first_line = if header.is_some() { 2 } else { 1 };
+    let mut listens = Vec::new();
+    for (line_no, record) in first.into_iter().map(Ok).chain(records).enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                warn!(
+                    "skipping invalid scrobble at line {}: {err}",
+                    line_no + first_line
+                );
+                continue;
+            }
+        };
+        let field = |idx: Option<usize>| {
+            idx.and_then(|idx| record.get(idx))
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+        };
+        let (Some(artist_name), Some(track_name)) = (field(Some(artist)), field(Some(title)))
+        else {
+            warn!(
+                "skipping scrobble at line {}, it doesn't have artist or title",
+                line_no + first_line
+            );
+            continue;
+        };
+        listens.push(Listen {
+            artist: artist_name.to_owned(),
+            title: track_name.to_owned(),
+            album: field(album).map(str::to_owned),
+            time: field(time).and_then(parse_lastfm_time),
+        });
+    }
+    Ok(listens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listenbrainz_array() {
+        let export = r#" [
+            {"listened_at": 1700000000, "track_metadata": {"artist_name": "Artist", "track_name": "Song", "release_name": "Album"}},
+            {"track_metadata": {"artist_name": "Other", "track_name": "Track", "release_name": ""}}
+        ]"#;
+        let listens = read_listens(ListenSource::ListenBrainz, export.as_bytes()).unwrap();
+        assert_eq!(listens.len(), 2);
+        assert_eq!(listens[0].artist, "Artist");
+        assert_eq!(listens[0].title, "Song");
+        assert_eq!(listens[0].album.as_deref(), Some("Album"));
+        assert_eq!(listens[0].time.unwrap().timestamp(), 1700000000);
+        assert_eq!(listens[1].album, None);
+        assert_eq!(listens[1].time, None);
+    }
+
+    #[test]
+    fn listenbrainz_lines() {
+        let export = concat!(
+            r#"{"listened_at": 1, "track_metadata": {"artist_name": "A", "track_name": "T"}}"#,
+            "\n\nnot json\n",
+            r#"{"listened_at": 2, "track_metadata": {"artist_name": "B", "track_name": "U"}}"#,
+            "\n"
+        );
+        let listens = read_listens(ListenSource::ListenBrainz, export.as_bytes()).unwrap();
+        let titles: Vec<_> = listens.iter().map(|listen| listen.title.as_str()).collect();
+        assert_eq!(titles, ["T", "U"]);
+    }
+
+    #[test]
+    fn invalid_listenbrainz_array() {
+        assert!(read_listens(ListenSource::ListenBrainz, "[{".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn lastfm_without_header() {
+        let export = "Artist,Album,Song,31 Jan 2021 12:34\nOther,,Track,\n,Album,Missing,\n";
+        let listens = read_listens(ListenSource::Lastfm, export.as_bytes()).unwrap();
+        assert_eq!(listens.len(), 2);
+        assert_eq!(listens[0].album.as_deref(), Some("Album"));
+        assert_eq!(
+            listens[0].time,
+            parse_lastfm_time("31 Jan 2021, 12:34"),
+            "both time formats are same"
+        );
+        assert!(listens[0].time.is_some());
+        assert_eq!(listens[1].artist, "Other");
+        assert_eq!(listens[1].album, None);
+    }
+
+    #[test]
+    fn lastfm_with_header() {
+        let export = "uts,utc_time,artist,album,track\n\
+            1600000000,\"13 Sep 2020, 12:26\",Artist,\"Album, Deluxe\",Song\n";
+        let listens = read_listens(ListenSource::Lastfm, export.as_bytes()).unwrap();
+        assert_eq!(listens.len(), 1);
+        assert_eq!(listens[0].title, "Song");
+        assert_eq!(listens[0].album.as_deref(), Some("Album, Deluxe"));
+        assert_eq!(listens[0].time.unwrap().timestamp(), 1600000000);
+    }
+
+    #[test]
+    fn lastfm_time() {
+        assert_eq!(parse_lastfm_time("0").unwrap().timestamp(), 0);
+        assert_eq!(
+            parse_lastfm_time("01 Jan 1970 00:01").unwrap().timestamp(),
+            60
+        );
+        assert_eq!(parse_lastfm_time("yesterday"), None);
+    }
+
+    #[test]
+    fn empty_exports() {
+        for source in [ListenSource::ListenBrainz, ListenSource::Lastfm] {
+            assert!(read_listens(source, "".as_bytes()).unwrap().is_empty());
+        }
+    }
+}
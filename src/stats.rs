@@ -1,13 +1,19 @@
 //! This module has functions related to statitics, manually setting them and displaying them.
 use crate::{
     error::{CustomEror, Error},
-    ConnType, MP_DESC, ROOT_DIR,
+    filter, history,
+    output::{self, OutputFormat},
+    players::{self, Player},
+    scrobble::{self, ListenSource},
+    store::{audio_hash, StatsStore},
+    ConnType, ROOT_DIR,
 };
+use chrono::{DateTime, Datelike, Local, NaiveDate, Utc};
 use clap::{Args, ValueEnum};
-use id3::{frame::Comment, Tag};
-use log::{debug, error, info, trace, warn};
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::{HashMap, HashSet},
     io::prelude::*,
     path::{self, PathBuf},
     process::exit,
@@ -20,30 +26,197 @@ use std::{
 //   Reset,
 // }
 
+/// play and skip counts of a month, used to weight them by their age for time decayed rating
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct PeriodStats {
+    /// first day of the month
+    pub month: NaiveDate,
+    /// number of times a song is played completely in the month.
+    pub play_cnt: u32,
+    /// number of times a song is skipped in the month.
+    pub skip_cnt: u32,
+}
+
 /// stores statistics in the form of played count and skipped count. using these perticular song
 /// can be rated.
-#[derive(Debug, Deserialize, Serialize, Default)]
+/// Timestamps are optional, so that stats stored by older versions are still valid
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
 pub struct Statistics {
     /// number of times a song is played completely.
     pub play_cnt: u32,
     /// number of times a song is skipped.
     pub skip_cnt: u32,
+    /// last time the song is played completely
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_played: Option<DateTime<Utc>>,
+    /// last time the song is skipped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_skipped: Option<DateTime<Utc>>,
+    /// first time the song is played or skipped, it is not known for songs played before it was
+    /// tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<DateTime<Utc>>,
+    /// play and skip counts of every month, sorted by month. Counts recorded before periods were
+    /// tracked are not part of it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub periods: Vec<PeriodStats>,
+    /// rating given manually by the user in range 0-10, it overrides the computed rating
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_rating: Option<f32>,
 }
 
 impl Statistics {
+    /// returns stats of the month of `now`, creates one if it doesn't exist
+    fn current_period(&mut self, now: DateTime<Utc>) -> &mut PeriodStats {
+        let month = now
+            .date_naive()
+            .with_day(1)
+            .expect("first day exists in every month");
+        if self
+            .periods
+            .last()
+            .is_none_or(|period| period.month != month)
+        {
+            self.periods.push(PeriodStats {
+                month,
+                play_cnt: 0,
+                skip_cnt: 0,
+            });
+        }
+        self.periods.last_mut().expect("period is just inserted")
+    }
+    /// creates stats of a single play at `time`
+    fn from_play(time: Option<DateTime<Utc>>) -> Self {
+        let mut stats = Self {
+            play_cnt: 1,
+            last_played: time,
+            first_seen: time,
+            ..Default::default()
+        };
+        if let Some(time) = time {
+            stats.current_period(time).play_cnt += 1;
+        }
+        stats
+    }
+    /// sets first seen time if the song is never played or skipped before
+    fn seen(&mut self, now: DateTime<Utc>) {
+        if self.play_cnt == 0 && self.skip_cnt == 0 {
+            self.first_seen.get_or_insert(now);
+        }
+    }
     /// increments skip count
     pub fn skipped(&mut self) {
+        let now = Utc::now();
+        self.seen(now);
         self.skip_cnt += 1;
+        self.last_skipped = Some(now);
+        self.current_period(now).skip_cnt += 1;
     }
     /// increments the play count
     pub fn played(&mut self) {
+        let now = Utc::now();
+        self.seen(now);
         self.play_cnt += 1;
+        self.last_played = Some(now);
+        self.current_period(now).play_cnt += 1;
+    }
+    /// returns play and skip counts where each count is weighted by its age, weight is halved for
+    /// every `half_life_days`. Age of a period is taken from the middle of the month.
+    /// Counts which are not part of any period are recorded before periods were tracked, so they
+    /// are aged from the last play or skip, but not later than the first period. Those counts are
+    /// not decayed if the song has neither.
+    fn decayed_counts(&self, half_life_days: f32, now: DateTime<Utc>) -> (f32, f32) {
+        let weight = |time: DateTime<Utc>| {
+            let age_days = (now - time).num_seconds().max(0) as f32 / SECS_PER_DAY;
+            0.5f32.powf(age_days / half_life_days)
+        };
+        let (mut play, mut skip) = (0.0, 0.0);
+        let (mut dated_play, mut dated_skip) = (0, 0);
+        for period in &self.periods {
+            let middle = period
+                .month
+                .and_hms_opt(0, 0, 0)
+                .expect("midnight is a valid time")
+                .and_utc()
+                + chrono::Duration::days(15);
+            let weight = weight(middle.min(now));
+            play += period.play_cnt as f32 * weight;
+            skip += period.skip_cnt as f32 * weight;
+            dated_play += period.play_cnt;
+            dated_skip += period.skip_cnt;
+        }
+        let first_period = self.periods.iter().map(|period| {
+            period
+                .month
+                .and_hms_opt(0, 0, 0)
+                .expect("midnight is a valid time")
+                .and_utc()
+        });
+        let last_time = match (self.last_played, self.last_skipped) {
+            (Some(t1), Some(t2)) => Some(t1.max(t2)),
+            (t1, t2) => t1.or(t2),
+        };
+        let undated = earliest(last_time, first_period.min()).unwrap_or(now);
+        let weight = weight(undated.min(now));
+        play += self.play_cnt.saturating_sub(dated_play) as f32 * weight;
+        skip += self.skip_cnt.saturating_sub(dated_skip) as f32 * weight;
+        (play, skip)
+    }
+    /// returns manual rating of the song if it is rated, else rating computed using the rating
+    /// algorithm
+    pub fn get_ratings(&self, rating: &RatingConfig) -> f32 {
+        self.user_rating
+            .unwrap_or_else(|| self.computed_rating(rating))
+    }
+    /// returns ratings of the song computed using the rating algorithm
+    pub fn computed_rating(&self, rating: &RatingConfig) -> f32 {
+        let play = self.play_cnt as f32;
+        let skip = self.skip_cnt as f32;
+        let total = play + skip;
+        match rating.algorithm {
+            RatingAlgorithm::Classic => (play / (1.0 + skip)) * total - skip,
+            RatingAlgorithm::Ratio if total == 0.0 => 0.0,
+            RatingAlgorithm::Ratio => 10.0 * play / total,
+            RatingAlgorithm::Wilson if total == 0.0 => 0.0,
+            RatingAlgorithm::Wilson => {
+                let z = WILSON_Z;
+                let ratio = play / total;
+                let z2_n = z * z / total;
+                10.0 * (ratio + z2_n / 2.0
+                    - z * ((ratio * (1.0 - ratio) + z2_n / 4.0) / total).sqrt())
+                    / (1.0 + z2_n)
+            }
+            RatingAlgorithm::Bayesian => bayesian_average(play, total),
+            RatingAlgorithm::Decayed => self.decayed_rating(rating.half_life),
+        }
+    }
+    /// returns bayesian average of the time decayed play and skip counts in range 0-10
+    pub fn decayed_rating(&self, half_life_days: f32) -> f32 {
+        let (play, skip) = self.decayed_counts(half_life_days, Utc::now());
+        bayesian_average(play, play + skip)
     }
-    /// returns ratings which is a number between 0-10 if there are ratings else None
-    pub fn get_ratings(&self) -> f32 {
-        (self.play_cnt as f32 / (1 + self.skip_cnt) as f32) * (self.play_cnt + self.skip_cnt) as f32
-            - self.skip_cnt as f32
+}
+
+/// returns the earliest of both times, None is considered unknown
+fn earliest(t1: Option<DateTime<Utc>>, t2: Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+    match (t1, t2) {
+        (Some(t1), Some(t2)) => Some(t1.min(t2)),
+        (t1, t2) => t1.or(t2),
+    }
+}
+
+/// merges the periods of both stats, counts of the same month are added
+fn merge_periods(mut periods: Vec<PeriodStats>, other: Vec<PeriodStats>) -> Vec<PeriodStats> {
+    for period in other {
+        match periods.binary_search_by_key(&period.month, |p| p.month) {
+            Ok(pos) => {
+                periods[pos].play_cnt += period.play_cnt;
+                periods[pos].skip_cnt += period.skip_cnt;
+            }
+            Err(pos) => periods.insert(pos, period),
+        }
     }
+    periods
 }
 
 impl std::ops::Add for Statistics {
@@ -53,14 +226,162 @@ impl std::ops::Add for Statistics {
         Self {
             skip_cnt: self.skip_cnt + rhs.skip_cnt,
             play_cnt: self.play_cnt + rhs.play_cnt,
+            last_played: self.last_played.max(rhs.last_played),
+            last_skipped: self.last_skipped.max(rhs.last_skipped),
+            first_seen: earliest(self.first_seen, rhs.first_seen),
+            periods: merge_periods(self.periods, rhs.periods),
+            // manual rating can't be added, so left one has higher priority
+            user_rating: self.user_rating.or(rhs.user_rating),
         }
     }
 }
 
 impl std::ops::AddAssign for Statistics {
     fn add_assign(&mut self, rhs: Self) {
-        self.play_cnt += rhs.play_cnt;
-        self.skip_cnt += rhs.skip_cnt;
+        *self = std::mem::take(self) + rhs;
+    }
+}
+
+/// formats the time in local timezone, if time is not known then `never`
+fn format_time(time: Option<DateTime<Utc>>) -> String {
+    time.map_or_else(
+        || "never".to_string(),
+        |time| {
+            time.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        },
+    )
+}
+
+/// z score for 95% confidence used in wilson score
+const WILSON_Z: f32 = 1.96;
+/// number of imaginary plays/skips added to every song in bayesian average
+const BAYESIAN_PRIOR_WEIGHT: f32 = 5.0;
+/// ratio of plays in the imaginary plays/skips of bayesian average
+const BAYESIAN_PRIOR_MEAN: f32 = 0.5;
+
+/// seconds in a day
+const SECS_PER_DAY: f32 = 86400.0;
+
+/// returns bayesian average of the play ratio in range 0-10
+fn bayesian_average(play: f32, total: f32) -> f32 {
+    10.0 * (BAYESIAN_PRIOR_WEIGHT * BAYESIAN_PRIOR_MEAN + play) / (BAYESIAN_PRIOR_WEIGHT + total)
+}
+
+/// formula used to compute rating from the stats
+#[derive(Debug, ValueEnum, Clone, Copy)]
+pub enum RatingAlgorithm {
+    /// (play/(1+skip))*(play+skip) - skip, it is unbounded and can be negative
+    Classic,
+    /// ratio of plays to plays and skips, in range 0-10
+    Ratio,
+    /// lower bound of wilson score interval of the play ratio in range 0-10, songs with few
+    /// plays/skips are rated lower as there is less confidence
+    Wilson,
+    /// bayesian average of the play ratio in range 0-10, songs with few plays/skips are rated
+    /// close to 5
+    Bayesian,
+    /// bayesian average where each play/skip is weighted by its age, weight halves every
+    /// half-life. Songs which are not played recently are rated close to 5
+    Decayed,
+}
+
+/// options to compute the rating of the songs
+#[derive(Args, Debug)]
+pub struct RatingConfig {
+    /// algorithm to compute the rating
+    #[arg(value_enum, long = "rating-algorithm", env = "MSCOUT_RATING_ALGORITHM", default_value_t = RatingAlgorithm::Classic)]
+    algorithm: RatingAlgorithm,
+    /// half life in days for decayed rating, plays and skips older than this are weighted half
+    #[arg(long, env = "MSCOUT_HALF_LIFE", default_value_t = 180.0, value_parser = parse_half_life)]
+    half_life: f32,
+}
+
+/// parses the half life, which should be a positive number of days
+fn parse_half_life(half_life: &str) -> Result<f32, String> {
+    let half_life: f32 = half_life.parse().map_err(|err| format!("{err}"))?;
+    if half_life.is_finite() && half_life > 0.0 {
+        Ok(half_life)
+    } else {
+        Err("half life should be more than 0 days".to_string())
+    }
+}
+
+/// rating used for sorting by stats
+#[derive(Debug, ValueEnum, Clone, Copy)]
+pub enum RatingSource {
+    /// manual rating if the song is rated, else computed rating
+    Effective,
+    /// rating computed from the stats
+    Computed,
+    /// manual rating, songs which are not rated come first
+    User,
+}
+
+impl RatingSource {
+    /// returns the rating of the stats from this source
+    fn rating(&self, stats: &Statistics, rating: &RatingConfig) -> Option<f32> {
+        match self {
+            RatingSource::Effective => Some(stats.get_ratings(rating)),
+            RatingSource::Computed => Some(stats.computed_rating(rating)),
+            RatingSource::User => stats.user_rating,
+        }
+    }
+}
+
+/// filters to select the songs based on their stats
+#[derive(Args, Debug)]
+pub struct StatsFilter {
+    /// select songs with rating at least this, manual rating is used if the song is rated
+    #[arg(long)]
+    min_rating: Option<f32>,
+    /// select songs with rating at most this, manual rating is used if the song is rated
+    #[arg(long)]
+    max_rating: Option<f32>,
+    /// select songs played at least these many times
+    #[arg(long)]
+    min_plays: Option<u32>,
+    /// select songs skipped at least these many times
+    #[arg(long)]
+    min_skips: Option<u32>,
+    /// select songs skipped at most these many times
+    #[arg(long)]
+    max_skips: Option<u32>,
+    /// select songs played after this time, date(YYYY-MM-DD), rfc3339 time or days(30d)/weeks(4w) ago
+    #[arg(long, value_parser = history::parse_time)]
+    played_since: Option<DateTime<Utc>>,
+    /// select songs not played after this time, songs never played are selected as well
+    #[arg(long, value_parser = history::parse_time)]
+    not_played_since: Option<DateTime<Utc>>,
+}
+
+impl StatsFilter {
+    /// returns true if the stats satisfies all the filters, `rating` is used to compute the rating
+    pub fn matches(&self, stats: &Statistics, rating: &RatingConfig) -> bool {
+        let song_rating = stats.get_ratings(rating);
+        self.min_rating.is_none_or(|min| song_rating >= min)
+            && self.max_rating.is_none_or(|max| song_rating <= max)
+            && self.min_plays.is_none_or(|min| stats.play_cnt >= min)
+            && self.min_skips.is_none_or(|min| stats.skip_cnt >= min)
+            && self.max_skips.is_none_or(|max| stats.skip_cnt <= max)
+            && self
+                .played_since
+                .is_none_or(|since| stats.last_played.is_some_and(|last| last >= since))
+            && self
+                .not_played_since
+                .is_none_or(|since| stats.last_played.is_none_or(|last| last < since))
+    }
+
+    /// returns true if any of the filters is given
+    fn is_active(&self) -> bool {
+        self.min_rating.is_some()
+            || self.max_rating.is_some()
+            || self.min_plays.is_some()
+            || self.min_skips.is_some()
+            || self.max_skips.is_some()
+            || self.played_since.is_some()
+            || self.not_played_since.is_some()
     }
 }
 
@@ -73,182 +394,72 @@ pub enum SortOrder {
     PlayCount,
     /// sort by skip count
     SkipCount,
+    /// sort by last played time
+    LastPlayed,
+    /// sort by last skipped time
+    LastSkipped,
+    /// sort by first time the song is played or skipped
+    FirstSeen,
+    /// sort by time decayed rating, recently played songs come last
+    Decayed,
+}
+
+/// tag by which the stats are grouped
+#[derive(Debug, ValueEnum, Clone, Copy)]
+pub enum GroupBy {
+    /// group by artist of the songs
+    Artist,
+    /// group by album of the songs
+    Album,
+    /// group by album artist, artist is used if the song doesn't have album artist
+    #[value(name = "albumartist")]
+    AlbumArtist,
+    /// group by genre of the songs
+    Genre,
+    /// group by directory of the songs
+    Directory,
+}
+
+impl GroupBy {
+    /// returns the value of the tag of the song
+    fn tag<'a>(&self, song: &'a mpd::Song) -> Option<&'a str> {
+        match self {
+            GroupBy::Artist => song.artist.as_deref(),
+            GroupBy::Album => song_tag(song, "Album"),
+            GroupBy::AlbumArtist => song_tag(song, "AlbumArtist").or(song.artist.as_deref()),
+            GroupBy::Genre => song_tag(song, "Genre"),
+            GroupBy::Directory => None,
+        }
+    }
 }
 
 /// Key to match when importing stats
-// for future implementation
-#[allow(dead_code)]
 enum ImportMethod<'a> {
     /// Full path of the song(from mpd_root directory)
     FullPath(&'a str),
     /// Compare just filename
     FileName(&'a str),
-    /// Match using title from the tag
-    Title(&'a str),
+    /// Match using title from the tag, artist and album are matched if they are known
+    Title {
+        /// title of the song
+        title: &'a str,
+        /// artist of the song
+        artist: Option<&'a str>,
+        /// album of the song
+        album: Option<&'a str>,
+    },
     /// Generate the hash of the song and match
-    Hash(u64),
-    /// Mtach using trackid of the tag
-    TrackId(u64),
-}
-
-/// gets the stats from mpd sticker database.
-/// where spath is the path to the song relative to mpd's directory
-pub fn stats_from_sticker(
-    client: &mut mpd::Client<ConnType>,
-    spath: &std::path::Path,
-) -> Result<Statistics, Error> {
-    trace!("getting stats from  mpd database for {:?}", spath);
-    // get the stats from sticker, if not found then return 0,0
-    client
-        .sticker("song", spath.to_str().unwrap(), MP_DESC)
-        .map_or_else(
-            |err| {
-                debug!("error {err} while getting stats");
-                match err {
-                    mpd::error::Error::Parse(_) => Ok(Statistics {
-                        play_cnt: 0,
-                        skip_cnt: 0,
-                    }),
-                    mpd::error::Error::Server(_) => Err(Error::FileNotExists),
-                    _ => Err(Error::ConnectionFailed),
-                }
-            },
-            |sticker| {
-                Ok(serde_json::from_str(&sticker).unwrap_or_else(|err| {
-                    warn!("couldn't parse sticker: {:?}", err);
-                    client
-                        .delete_sticker("song", spath.to_str().unwrap(), MP_DESC) // if the sticker is invalid then remove it.
-                        .unwrap_or_else(|err| warn!("failed to delete sticker {:?}", err));
-                    Statistics {
-                        play_cnt: 0,
-                        skip_cnt: 0,
-                    }
-                }))
-            },
-        )
+    Hash(&'a str),
+    /// Mtach using musicbrainz trackid of the tag
+    TrackId(&'a str),
 }
 
-/// set the stats to mpd sticker database.
-/// where spath is the path to the song relative to mpd's directory
-pub fn stats_to_sticker(
-    client: &mut mpd::Client<ConnType>,
-    spath: &std::path::Path,
-    stats: &Statistics,
-) -> Result<(), Error> {
-    info!("setting stats {:?} to mpd database for {:?}", stats, spath);
-    client
-        .set_sticker(
-            "song",
-            spath.to_str().unwrap(),
-            MP_DESC,
-            &serde_json::to_string(stats).expect("Couldn't dump stats to json"),
-        )
-        .map_err(|err| {
-            error!("Couldn't dump to mpd  database due to {:?}", err);
-            Error::ConnectionFailed
-        })?;
-    Ok(())
-}
-
-/// extracts the statistics from eyed3 tags(from comments).
-pub fn stats_from_tag(rel_path: &std::path::Path) -> Result<Statistics, Error> {
-    let song_pbuff = if rel_path.is_file() {
-        path::PathBuf::from(rel_path)
-    } else {
-        path::PathBuf::from(ROOT_DIR.get().expect("statistics to tag requires full path, try to use --socket-file or set root-dir manually")).join(rel_path)
-    };
-    let mut cmt = None;
-    debug!("songs full path is {:#?}", song_pbuff);
-    let mut tag = Tag::read_from_path(&song_pbuff).or_else(|err: id3::Error| match err.kind {
-        id3::ErrorKind::NoTag => {
-            warn!("no tag found creating a new id3 tag");
-            Ok(Tag::new())
-        }
-        id3::ErrorKind::StringDecoding(..) => {
-            error!(
-                "invalid input error while reading tag {:?} for song {:?}",
-                err.description, rel_path,
-            );
-            Err(Error::Id3ReadTag)
-        }
-        _ => {
-            error!(
-                "unknown error while reading tag {:?} for song {:?}",
-                err.description, rel_path,
-            );
-            Err(Error::Unknown)
-        }
-    })?;
-    // return Err(Error::FileNotExists);
-    for com in tag.comments() {
-        debug!("available comments are {:?}", com);
-        if com.description == MP_DESC {
-            cmt = Some(com.clone());
-            break;
-        }
-    }
-    // if the file has ratings comment then modify it, else create fresh one with 0 0
-    cmt.map_or_else(
-        || {
-            let stats = Statistics {
-                play_cnt: 0,
-                skip_cnt: 0,
-            };
-            let comment = Comment {
-                lang: "eng".to_string(),
-                description: MP_DESC.to_string(),
-                text: serde_json::to_string(&stats).expect("couldn't convert ratings  to json"),
-            };
-            tag.add_comment(comment);
-            tag.write_to_path(song_pbuff, id3::Version::Id3v24)
-                .unwrap_or_else(|err| warn!("Failed to write tag : {}", err.description));
-            Ok(stats)
-        },
-        |comment| {
-            let rating: Statistics = serde_json::from_str(&comment.text).unwrap_or_else(|err| {
-                warn!(
-                    "err {} invalid json text for rating comment {}",
-                    err, comment.text
-                );
-                Statistics {
-                    play_cnt: 0,
-                    skip_cnt: 0,
-                }
-            });
-            Ok(rating)
-        },
-    )
-}
-
-/// set the statistics to the eyed3 tags(from comments).
-/// spath : absolute path to the song.
-pub fn stats_to_tag(spath: &std::path::Path, stats: &Statistics) -> Result<(), Error> {
-    let song_pbuff = if spath.is_file() {
-        path::PathBuf::from(spath)
-    } else {
-        path::PathBuf::from(ROOT_DIR.get().expect("statistics to tag requires full path, try to use --socket-file or set root-dir manually")).join(spath)
-    };
-    debug!("setting tag to {:#?}", song_pbuff);
-    let mut tag = Tag::read_from_path(&song_pbuff).or_else(|err: id3::Error| match err.kind {
-        id3::ErrorKind::NoTag => {
-            warn!("no tag found creating a new id3 tag");
-            Ok(Tag::new())
-        }
-        _ => {
-            error!(" error while opening tag {:?}", err.description);
-            Err(Error::FileNotExists)
-        }
-    })?;
-    let comment: Comment = Comment {
-        lang: "eng".to_string(),
-        description: MP_DESC.to_string(),
-        text: serde_json::to_string(stats).expect("couldn't convert ratings  to json"),
-    };
-    info!("attaching tag comment {:?}", comment);
-    tag.add_comment(comment);
-    tag.write_to_path(&song_pbuff, id3::Version::Id3v24)
-        .unwrap_or_else(|err| warn!("failed to write tag {}", err));
-    Ok(())
+/// returns the first value of the tag from mpd's song, tag names are case insensitive
+pub fn song_tag<'a>(song: &'a mpd::Song, name: &str) -> Option<&'a str> {
+    song.tags
+        .iter()
+        .find(|(tag, _)| tag.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
 }
 
 /// Configuration options for SetStats
@@ -278,6 +489,32 @@ pub struct SetStatsConfig {
     stats: Option<String>,
 }
 
+/// Configuration options for Rate
+#[derive(Args, Debug)]
+pub struct RateConfig {
+    /// rate current song
+    #[arg(short, long, group("selection"))]
+    current: bool,
+    /// rate previous song in the queue
+    #[arg(short, long, group("selection"))]
+    previous: bool,
+    /// rate next song in the queue
+    #[arg(short, long, group("selection"))]
+    next: bool,
+    /// relative path from music directory configured in mpd
+    #[arg(group("selection"))]
+    path: Option<String>,
+    /// rating of the song in range 0-10, or 0-5 if --stars is given
+    #[arg(short = 'R', long, required_unless_present("clear"))]
+    rating: Option<f32>,
+    /// rating is given in 0-5 stars
+    #[arg(long)]
+    stars: bool,
+    /// removes the manual rating, so that computed rating is used
+    #[arg(long, conflicts_with("rating"))]
+    clear: bool,
+}
+
 /// Configuration Options for GetStats
 #[derive(Args, Debug)]
 pub struct GetStatsConfig {
@@ -306,16 +543,223 @@ pub struct GetStatsConfig {
     /// prints the exact stats instead of a single rating number
     #[arg(short, long)]
     stats: bool,
-    /// print stats in json format
-    #[arg(short, long)]
+    /// options to compute the rating
+    #[command(flatten)]
+    rating: RatingConfig,
+    /// rating used when sorting by stats
+    #[arg(value_enum, long, default_value_t = RatingSource::Effective)]
+    rating_source: RatingSource,
+    /// print stats in json format, same as `--format json`
+    #[arg(short, long, conflicts_with("format"))]
     json: bool,
+    /// print stats in the format, csv and tsv include the tags of the songs
+    #[arg(value_enum, long)]
+    format: Option<OutputFormat>,
+    /// prints each song with the jinja template, `\t` and `\n` are replaced with tab and newline.
+    /// Template gets `path`, `play`, `skip`, `rating`, `computed`, `user_rating`, `last_played`,
+    /// `last_skipped`, `first_seen`, `title`, `artist`, `album` and song's `tags`
+    #[arg(short, long, conflicts_with_all(["json", "format", "group_by"]))]
+    template: Option<String>,
+    /// prints last played, last skipped and first seen times of the songs
+    #[arg(long)]
+    times: bool,
+    /// filters on the stats of the songs
+    #[command(flatten)]
+    stats_filter: StatsFilter,
+    /// mpd filter expression like `((artist == "X") AND (album contains 'Y'))`, only `AND` is
+    /// supported. If no songs are selected then all matching songs from the library are selected
+    #[arg(short, long, value_parser = filter::parse_filter)]
+    filter: Option<filter::FilterExpr>,
+    /// match the filter expression ignoring the case
+    #[arg(short, long, requires("filter"))]
+    ignore_case: bool,
+    /// prints the total stats and average rating of the songs grouped by the tag
+    #[arg(value_enum, short, long)]
+    group_by: Option<GroupBy>,
     /// relative path from music directory configured in mpd
     #[arg()]
     paths: Vec<String>,
 }
 
-/// extracts song statistics from id3 metadata or mpd's database based on use-tags flags
-pub fn get_stats(client: &mut mpd::Client<ConnType>, config: &GetStatsConfig, use_tags: bool) {
+impl GetStatsConfig {
+    /// returns the format of the output, if it is not plain text
+    fn output_format(&self) -> Option<OutputFormat> {
+        self.format.or(self.json.then_some(OutputFormat::Json))
+    }
+}
+
+/// sorts the items in the order given by the config, `key` returns the stats of the item and the
+/// rating used for sorting by stats
+fn sort_stats<T>(
+    items: &mut [T],
+    config: &GetStatsConfig,
+    key: impl Fn(&T) -> (&Statistics, Option<f32>),
+) {
+    let half_life = config.rating.half_life;
+    items.sort_by(|i1, i2| {
+        let ((s1, r1), (s2, r2)) = (key(i1), key(i2));
+        let order = match config.sort {
+            SortOrder::Stats => match (r1, r2) {
+                (Some(r1), Some(r2)) => r1.total_cmp(&r2),
+                // songs which are not rated come first
+                (r1, r2) => r1.is_some().cmp(&r2.is_some()),
+            },
+            SortOrder::PlayCount => s1.play_cnt.cmp(&s2.play_cnt),
+            SortOrder::SkipCount => s1.skip_cnt.cmp(&s2.skip_cnt),
+            // songs which are never played/skipped are considered oldest
+            SortOrder::LastPlayed => s1.last_played.cmp(&s2.last_played),
+            SortOrder::LastSkipped => s1.last_skipped.cmp(&s2.last_skipped),
+            SortOrder::FirstSeen => s1.first_seen.cmp(&s2.first_seen),
+            SortOrder::Decayed => s1
+                .decayed_rating(half_life)
+                .total_cmp(&s2.decayed_rating(half_life)),
+        };
+        if config.reverse {
+            order.reverse()
+        } else {
+            order
+        }
+    });
+}
+
+/// stats of the songs grouped by a tag
+#[derive(Debug, Serialize)]
+struct GroupStats {
+    /// value of the tag, songs without the tag are grouped under `unknown`
+    name: String,
+    /// number of songs in the group
+    songs: usize,
+    /// average rating of the songs, songs without rating are ignored
+    rating: Option<f32>,
+    /// sum of the stats of all the songs
+    #[serde(flatten)]
+    stats: Statistics,
+}
+
+/// groups the stats of the songs by the tag, tags are taken from mpd's library
+fn group_stats(
+    client: &mut mpd::Client<ConnType>,
+    songs: Vec<(String, Statistics)>,
+    group_by: GroupBy,
+    config: &GetStatsConfig,
+) -> Vec<GroupStats> {
+    let library: HashMap<String, mpd::Song> = match group_by {
+        GroupBy::Directory => HashMap::new(),
+        _ => library_info(client),
+    };
+    let mut groups: HashMap<String, (usize, Statistics, Vec<f32>)> = HashMap::new();
+    for (song, stats) in songs {
+        let name = match group_by {
+            GroupBy::Directory => path::Path::new(&song)
+                .parent()
+                .map(|dir| dir.to_string_lossy().into_owned()),
+            _ => library
+                .get(&song)
+                .and_then(|info| group_by.tag(info))
+                .map(str::to_owned),
+        }
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+        let rating = config.rating_source.rating(&stats, &config.rating);
+        let group = groups.entry(name).or_default();
+        group.0 += 1;
+        group.1 += stats;
+        group.2.extend(rating);
+    }
+    groups
+        .into_iter()
+        .map(|(name, (songs, mut stats, ratings))| {
+            // manual rating of a song is not the rating of the group
+            stats.user_rating = None;
+            GroupStats {
+                name,
+                songs,
+                rating: (!ratings.is_empty())
+                    .then(|| ratings.iter().sum::<f32>() / ratings.len() as f32),
+                stats,
+            }
+        })
+        .collect()
+}
+
+/// flat row of the group stats for csv and tsv output
+#[derive(Debug, Serialize)]
+struct GroupRow {
+    /// value of the tag
+    name: String,
+    /// number of songs in the group
+    songs: usize,
+    /// average rating of the songs
+    rating: Option<f32>,
+    /// total play count
+    play_cnt: u32,
+    /// total skip count
+    skip_cnt: u32,
+    /// last time any song of the group is played
+    last_played: Option<DateTime<Utc>>,
+    /// last time any song of the group is skipped
+    last_skipped: Option<DateTime<Utc>>,
+    /// first time any song of the group is played or skipped
+    first_seen: Option<DateTime<Utc>>,
+}
+
+impl GroupRow {
+    /// creates row from the group stats
+    fn new(group: &GroupStats) -> Self {
+        Self {
+            name: group.name.clone(),
+            songs: group.songs,
+            rating: group.rating,
+            play_cnt: group.stats.play_cnt,
+            skip_cnt: group.stats.skip_cnt,
+            last_played: group.stats.last_played,
+            last_skipped: group.stats.last_skipped,
+            first_seen: group.stats.first_seen,
+        }
+    }
+}
+
+/// prints the stats of the groups
+fn print_groups(groups: &[GroupStats], config: &GetStatsConfig) {
+    if let Some(format) = config.output_format() {
+        output::write_items(std::io::stdout(), format, groups, GroupRow::new)
+            .try_unwrap("Couldn't print the stats");
+        return;
+    }
+    for group in groups {
+        let times = if config.times {
+            format!(
+                "\tlast played: {}\tlast skipped: {}\tfirst seen: {}",
+                format_time(group.stats.last_played),
+                format_time(group.stats.last_skipped),
+                format_time(group.stats.first_seen)
+            )
+        } else {
+            String::new()
+        };
+        let rating = group
+            .rating
+            .map_or_else(|| "unrated".to_string(), |rating| format!("{rating:.2}"));
+        if config.stats {
+            println!(
+                "play count: {}\tskip count: {}\tsongs: {}{} - {}",
+                group.stats.play_cnt, group.stats.skip_cnt, group.songs, times, group.name
+            );
+        } else {
+            println!(
+                "{}\tsongs: {}{} - {}",
+                rating, group.songs, times, group.name
+            );
+        }
+    }
+}
+
+/// extracts song statistics from the given store
+pub fn get_stats(
+    client: &mut mpd::Client<ConnType>,
+    config: &GetStatsConfig,
+    store: &mut dyn StatsStore,
+) {
     let mut songs = Vec::new();
     if config.current {
         songs.push(path::PathBuf::from(
@@ -404,14 +848,39 @@ pub fn get_stats(client: &mut mpd::Client<ConnType>, config: &GetStatsConfig, us
         debug!("appending path {user_path} to songs list");
         songs.push(path::PathBuf::from(user_path));
     }
+    let selected = config.current
+        || config.previous
+        || config.next
+        || config.queue
+        || !config.playlists.is_empty()
+        || !config.paths.is_empty();
+    if let Some(expr) = &config.filter {
+        let matched: Vec<PathBuf> = expr
+            .songs(client, config.ignore_case)
+            .try_unwrap("Couldn't find the songs matching the filter")
+            .into_iter()
+            .map(|song| PathBuf::from(song.file))
+            .collect();
+        debug!("{} songs matched the filter expression", matched.len());
+        if selected {
+            let matched: HashSet<&PathBuf> = matched.iter().collect();
+            songs.retain(|song| matched.contains(song));
+        } else {
+            songs = matched;
+        }
+    } else if !selected && config.stats_filter.is_active() {
+        debug!("no songs are selected, filtering the whole library");
+        songs = client
+            .listall()
+            .try_unwrap("Couldn't get the songs from mpd")
+            .into_iter()
+            .map(|song| PathBuf::from(song.file))
+            .collect();
+    }
     // Collect ratings
     let mut with_ratings: Vec<(_, _)> = Vec::new();
     for song in songs {
-        if let Ok(rating) = if use_tags {
-            stats_from_tag(&song)
-        } else {
-            stats_from_sticker(client, &song)
-        } {
+        if let Ok(rating) = store.read(client, &song) {
             with_ratings.push((
                 song.to_str()
                     .expect("Failed to get the song name into string")
@@ -422,56 +891,114 @@ pub fn get_stats(client: &mut mpd::Client<ConnType>, config: &GetStatsConfig, us
             error!("Couldn't get the stats for {song:?}");
         }
     }
+    with_ratings.retain(|(_, stats)| config.stats_filter.matches(stats, &config.rating));
 
+    if let Some(group_by) = config.group_by {
+        let mut groups = group_stats(client, with_ratings, group_by, config);
+        sort_stats(&mut groups, config, |group: &GroupStats| {
+            (&group.stats, group.rating)
+        });
+        print_groups(&groups, config);
+        return;
+    }
     // Sort the songs by ratings
-    match config.sort {
-        SortOrder::Stats => {
-            with_ratings.sort_by(|s1, s2| {
-                if config.reverse {
-                    s2.1.get_ratings().partial_cmp(&s1.1.get_ratings()).unwrap()
-                } else {
-                    s1.1.get_ratings().partial_cmp(&s2.1.get_ratings()).unwrap()
-                }
-            });
-        }
-        SortOrder::PlayCount => {
-            with_ratings.sort_by(|s1, s2| {
-                if config.reverse {
-                    s2.1.play_cnt.partial_cmp(&s1.1.play_cnt).unwrap()
-                } else {
-                    s1.1.play_cnt.partial_cmp(&s2.1.play_cnt).unwrap()
-                }
-            });
-        }
-        SortOrder::SkipCount => {
-            with_ratings.sort_by(|s1, s2| {
-                if config.reverse {
-                    s2.1.skip_cnt.partial_cmp(&s1.1.skip_cnt).unwrap()
-                } else {
-                    s1.1.skip_cnt.partial_cmp(&s2.1.skip_cnt).unwrap()
-                }
+    let source = config.rating_source;
+    sort_stats(&mut with_ratings, config, |song: &(String, Statistics)| {
+        (&song.1, source.rating(&song.1, &config.rating))
+    });
+    // -------------- print all the stats----------------------------
+    if let Some(format) = config.output_format() {
+        let library = songs_info(client, &with_ratings);
+        let rows: Vec<StatsRow> = with_ratings
+            .iter()
+            .map(|(song, stats)| {
+                StatsRow::new(
+                    &SavedStats::new(song.clone(), library.get(song), stats.clone()),
+                    &config.rating,
+                )
+            })
+            .collect();
+        output::write_items(std::io::stdout(), format, &rows, StatsRow::clone)
+            .try_unwrap("Couldn't print the stats");
+    } else if let Some(template) = &config.template {
+        let template = template.replace("\\t", "\t").replace("\\n", "\n");
+        let mut jinja_env = minijinja::Environment::new();
+        jinja_env
+            .add_template("row", &template)
+            .unwrap_or_else(|err| {
+                error!("invalid template: {err}");
+                exit(1);
             });
+        let template = jinja_env
+            .get_template("row")
+            .expect("template is just added");
+        let library = songs_info(client, &with_ratings);
+        for (song, stats) in &with_ratings {
+            let info = library.get(song.as_str());
+            let tags: std::collections::BTreeMap<_, _> = info
+                .map(|info| info.tags.clone())
+                .unwrap_or_default()
+                .into_iter()
+                .collect();
+            let context = minijinja::context!(
+                path => song,
+                play => stats.play_cnt,
+                skip => stats.skip_cnt,
+                rating => stats.get_ratings(&config.rating),
+                computed => stats.computed_rating(&config.rating),
+                user_rating => stats.user_rating,
+                last_played => format_time(stats.last_played),
+                last_skipped => format_time(stats.last_skipped),
+                first_seen => format_time(stats.first_seen),
+                title => info.and_then(|info| info.title.clone()),
+                artist => info.and_then(|info| info.artist.clone()),
+                album => info.and_then(|info| song_tag(info, "Album")),
+                tags => tags,
+            );
+            match template.render(context) {
+                Ok(line) => println!("{line}"),
+                Err(err) => warn!("couldn't render the template for {song}: {err}"),
+            }
         }
-    }
-    // -------------- print all the stats----------------------------
-    if config.json {
-        println!("{}", serde_json::to_string(&with_ratings).unwrap());
     } else {
         for (song, rating) in with_ratings {
+            let times = if config.times {
+                format!(
+                    "\tlast played: {}\tlast skipped: {}\tfirst seen: {}",
+                    format_time(rating.last_played),
+                    format_time(rating.last_skipped),
+                    format_time(rating.first_seen)
+                )
+            } else {
+                String::new()
+            };
+            let user_rating = rating
+                .user_rating
+                .map_or_else(String::new, |user| format!("\tuser rating: {user}"));
             if config.stats {
                 println!(
-                    "play count: {}\tskip count: {} - {}",
-                    rating.play_cnt, rating.skip_cnt, song
+                    "play count: {}\tskip count: {}{}{} - {}",
+                    rating.play_cnt, rating.skip_cnt, user_rating, times, song
                 );
             } else {
-                println!("{} - {}", rating.get_ratings(), song);
+                println!(
+                    "{}{}{} - {}",
+                    rating.computed_rating(&config.rating),
+                    user_rating,
+                    times,
+                    song
+                );
             }
         }
     }
 }
 
 /// sets the stats of a custom user stats
-pub fn set_stats(client: &mut mpd::Client<ConnType>, config: &SetStatsConfig, use_tags: bool) {
+pub fn set_stats(
+    client: &mut mpd::Client<ConnType>,
+    config: &SetStatsConfig,
+    store: &mut dyn StatsStore,
+) {
     // get the song to set stats, if current is given then get it from mpd or else from path
     // argument
     let song_file = if config.current {
@@ -495,19 +1022,14 @@ pub fn set_stats(client: &mut mpd::Client<ConnType>, config: &SetStatsConfig, us
     let stat = if let Some(stats) = &config.stats {
         serde_json::from_str::<Statistics>(stats).try_unwrap("error while parsing parsing Stats")
     } else {
-        let mut curr_stat = if use_tags {
-            stats_from_tag(&song_file).unwrap_or_else(|err| {
-                if let Error::FileNotExists = err {
-                    error!("{:?} does'n exists", song_file);
-                }
-                exit(1);
-            })
-        } else {
-            stats_from_sticker(client, &song_file).unwrap_or_else(|err| {
-                error!("Couldn't Get the stats from sticker: {:?}", err);
-                exit(1);
-            })
-        };
+        let mut curr_stat = store.read(client, &song_file).unwrap_or_else(|err| {
+            if let Error::FileNotExists = err {
+                error!("{:?} does'n exists", song_file);
+            } else {
+                error!("Couldn't Get the stats: {:?}", err);
+            }
+            exit(1);
+        });
         if let Some(play_cnt) = config.play_cnt {
             curr_stat.play_cnt = play_cnt
         }
@@ -517,16 +1039,66 @@ pub fn set_stats(client: &mut mpd::Client<ConnType>, config: &SetStatsConfig, us
         curr_stat
     };
 
-    match if use_tags {
-        stats_to_tag(&song_file, &stat)
-    } else {
-        stats_to_sticker(client, &song_file, &stat)
-    } {
+    match store.write(client, &song_file, &stat) {
         Ok(_) => info!("stats {stat:?} set to {song_file:?}"),
         Err(_) => error!("Failed to set stats"),
     }
 }
 
+/// returns the song in the queue relative to current song, `offset` -1 is previous song and 1 is
+/// next song
+fn queue_song(client: &mut mpd::Client<ConnType>, offset: i64) -> Option<PathBuf> {
+    let current = client
+        .status()
+        .try_unwrap("Couldn't get the status from mpd")
+        .song?;
+    let pos = u32::try_from(current.pos as i64 + offset).ok()?;
+    client
+        .queue()
+        .try_unwrap("Couldn't get the queue information from mpd")
+        .into_iter()
+        .find(|song| song.place.is_some_and(|place| place.pos == pos))
+        .map(|song| PathBuf::from(song.file))
+}
+
+/// sets or clears the manual rating of the song
+pub fn rate_song(
+    client: &mut mpd::Client<ConnType>,
+    config: &RateConfig,
+    store: &mut dyn StatsStore,
+) {
+    let song_file = if config.current {
+        queue_song(client, 0)
+    } else if config.previous {
+        queue_song(client, -1)
+    } else if config.next {
+        queue_song(client, 1)
+    } else {
+        config.path.as_ref().map(PathBuf::from)
+    }
+    .unwrap_or_else(|| {
+        error!("Couldn't get the song to rate, give either path or one of (-c|-p|-n)");
+        exit(1);
+    });
+    let user_rating = match config.rating {
+        Some(rating) if config.stars => Some(rating * 2.0),
+        rating => rating,
+    };
+    if user_rating.is_some_and(|rating| !(0.0..=10.0).contains(&rating)) {
+        error!("rating should be in range 0-10 or 0-5 stars");
+        exit(1);
+    }
+    let mut stats = store.read(client, &song_file).unwrap_or_else(|err| {
+        error!("Couldn't Get the stats of {:?}: {:?}", song_file, err);
+        exit(1);
+    });
+    stats.user_rating = user_rating;
+    match store.write(client, &song_file, &stats) {
+        Ok(_) => info!("rating {user_rating:?} set to {song_file:?}"),
+        Err(_) => error!("Failed to set rating"),
+    }
+}
+
 /// struct used to export or import statistics of a song
 #[derive(Debug, Serialize, Deserialize)]
 struct SavedStats {
@@ -534,176 +1106,581 @@ struct SavedStats {
     path: String,
     /// optional hash of the song, if path doesn't matches then if hash matches, hash is used
     hash: Option<String>,
+    /// title of the song from the tags
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    /// artist of the song from the tags
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    artist: Option<String>,
+    /// album of the song from the tags
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    album: Option<String>,
+    /// musicbrainz track id of the song from the tags
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    track_id: Option<String>,
     /// statistics of the song
     stats: Statistics,
 }
 
-/// Returns reference to song from `song_list` based on ImportMethod
-fn get_song_by_key<'a>(key: &ImportMethod, song_list: &'a [mpd::Song]) -> Option<&'a mpd::Song> {
-    match key {
-        ImportMethod::FullPath(fpath) => {
-            for song in song_list {
-                if song.file == *fpath {
-                    return Some(song);
+impl SavedStats {
+    /// creates saved stats of the song, tag metadata is taken from `info` if available
+    fn new(path: String, info: Option<&mpd::Song>, stats: Statistics) -> Self {
+        Self {
+            path,
+            hash: None,
+            title: info.and_then(|song| song.title.clone()),
+            artist: info.and_then(|song| song.artist.clone()),
+            album: info.and_then(|song| song_tag(song, "Album").map(str::to_owned)),
+            track_id: info
+                .and_then(|song| song_tag(song, "MUSICBRAINZ_TRACKID").map(str::to_owned)),
+            stats,
+        }
+    }
+}
+
+/// flat row of the song stats for the formatted output of get-stats
+#[derive(Debug, Clone, Serialize)]
+struct StatsRow {
+    /// path from mpd's root directory
+    path: String,
+    /// title of the song from the tags
+    title: Option<String>,
+    /// artist of the song from the tags
+    artist: Option<String>,
+    /// album of the song from the tags
+    album: Option<String>,
+    /// musicbrainz track id of the song from the tags
+    track_id: Option<String>,
+    /// play count
+    play_cnt: u32,
+    /// skip count
+    skip_cnt: u32,
+    /// rating computed from the stats
+    rating: f32,
+    /// manual rating of the song
+    user_rating: Option<f32>,
+    /// last time the song is played
+    last_played: Option<DateTime<Utc>>,
+    /// last time the song is skipped
+    last_skipped: Option<DateTime<Utc>>,
+    /// first time the song is played or skipped
+    first_seen: Option<DateTime<Utc>>,
+    /// hash of the song if it is exported with hash
+    hash: Option<String>,
+}
+
+impl StatsRow {
+    /// creates row from the saved stats, `rating` is used to compute the rating
+    fn new(saved: &SavedStats, rating: &RatingConfig) -> Self {
+        Self {
+            path: saved.path.clone(),
+            title: saved.title.clone(),
+            artist: saved.artist.clone(),
+            album: saved.album.clone(),
+            track_id: saved.track_id.clone(),
+            play_cnt: saved.stats.play_cnt,
+            skip_cnt: saved.stats.skip_cnt,
+            rating: saved.stats.computed_rating(rating),
+            user_rating: saved.stats.user_rating,
+            last_played: saved.stats.last_played,
+            last_skipped: saved.stats.last_skipped,
+            first_seen: saved.stats.first_seen,
+            hash: saved.hash.clone(),
+        }
+    }
+}
+
+/// returns the song with its tags from mpd's library
+fn find_song(client: &mut mpd::Client<ConnType>, path: &str) -> Option<mpd::Song> {
+    let mut query = mpd::Query::new();
+    query.and(mpd::Term::File, path);
+    match client.find(&query, None) {
+        Ok(songs) => songs.into_iter().next(),
+        Err(err) => {
+            warn!("couldn't get the tags of {path} due to {err}");
+            None
+        }
+    }
+}
+
+/// returns all the songs of mpd's library with their tags, mapped by their path
+fn library_info(client: &mut mpd::Client<ConnType>) -> HashMap<String, mpd::Song> {
+    client
+        .listallinfo()
+        .try_unwrap("Couldn't get the songs from mpd")
+        .into_iter()
+        .map(|song| (song.file.clone(), song))
+        .collect()
+}
+
+/// returns details of the songs keyed by path, a single song, like the current song in status
+/// bars, doesn't need the whole library
+fn songs_info(
+    client: &mut mpd::Client<ConnType>,
+    songs: &[(String, Statistics)],
+) -> HashMap<String, mpd::Song> {
+    if songs.len() > 1 {
+        library_info(client)
+    } else {
+        songs
+            .iter()
+            .filter_map(|(song, _)| find_song(client, song))
+            .map(|info| (info.file.clone(), info))
+            .collect()
+    }
+}
+
+/// exits if mpd's music directory is not known, hashing requires access to song files
+fn require_root_dir() {
+    if ROOT_DIR.get().is_none() {
+        error!("hashing songs requires music directory, try to use --socket-path or set root-dir manually");
+        exit(1);
+    }
+}
+
+/// computes audio hash of all the songs, returns map of hash to the song
+fn hash_songs(song_list: &[mpd::Song]) -> HashMap<String, &mpd::Song> {
+    let mut progress = pbr::ProgressBar::on(std::io::stderr(), song_list.len() as u64);
+    progress.message("hashing songs ");
+    let mut hashes = HashMap::with_capacity(song_list.len());
+    for song in song_list {
+        match audio_hash(path::Path::new(&song.file)) {
+            Ok(hash) => {
+                if let Some(dup) = hashes.insert(hash, song) {
+                    warn!("{} and {} have same audio content", dup.file, song.file);
                 }
             }
-            None
+            Err(err) => warn!("couldn't hash {}, due to {:?}", song.file, err),
         }
+        progress.inc();
+    }
+    progress.finish_print("hashing done");
+    hashes
+}
+
+/// returns true if both strings are same ignoring the case
+fn eq_ignore_case(left: &str, right: &str) -> bool {
+    left.to_lowercase() == right.to_lowercase()
+}
+
+/// Returns references to songs from `song_list` which matches the ImportMethod.
+/// `hashes` is map of audio hash to song, required only for hash method
+fn get_song_by_key<'a>(
+    key: &ImportMethod,
+    song_list: &'a [mpd::Song],
+    hashes: &HashMap<String, &'a mpd::Song>,
+) -> Vec<&'a mpd::Song> {
+    match key {
+        ImportMethod::FullPath(fpath) => song_list
+            .iter()
+            .filter(|song| path::Path::new(&song.file) == path::Path::new(fpath))
+            .take(1)
+            .collect(),
         ImportMethod::FileName(fname) => {
             let key_path_buf = path::PathBuf::from(fname);
-            if let Some(file_name) = key_path_buf.file_name() {
-                for song in song_list.iter() {
-                    let file_path = path::PathBuf::from(&song.file);
-                    if file_path.file_name() == Some(file_name) {
-                        return Some(song);
-                    }
-                }
+            let Some(file_name) = key_path_buf.file_name() else {
+                return Vec::new();
+            };
+            song_list
+                .iter()
+                .filter(|song| path::Path::new(&song.file).file_name() == Some(file_name))
+                .collect()
+        }
+        ImportMethod::Title {
+            title,
+            artist,
+            album,
+        } => song_list
+            .iter()
+            .filter(|song| {
+                song.title
+                    .as_deref()
+                    .is_some_and(|song_title| eq_ignore_case(song_title, title))
+                    && artist.is_none_or(|artist| {
+                        song.artist
+                            .as_deref()
+                            .is_some_and(|song_artist| eq_ignore_case(song_artist, artist))
+                    })
+                    && album.is_none_or(|album| {
+                        song_tag(song, "Album")
+                            .is_some_and(|song_album| eq_ignore_case(song_album, album))
+                    })
+            })
+            .collect(),
+        ImportMethod::Hash(hash) => hashes.get(*hash).copied().into_iter().collect(),
+        ImportMethod::TrackId(track_id) => song_list
+            .iter()
+            .filter(|song| {
+                song_tag(song, "MUSICBRAINZ_TRACKID")
+                    .is_some_and(|song_track_id| eq_ignore_case(song_track_id, track_id))
+            })
+            .collect(),
+    }
+}
+
+/// converts the listens to stats, listens of the same artist, title and album are added
+fn listens_to_stats(listens: Vec<scrobble::Listen>) -> Vec<SavedStats> {
+    let mut songs: HashMap<(String, String, Option<String>), SavedStats> = HashMap::new();
+    for listen in listens {
+        let key = (
+            listen.artist.to_lowercase(),
+            listen.title.to_lowercase(),
+            listen.album.as_deref().map(str::to_lowercase),
+        );
+        let stats = Statistics::from_play(listen.time);
+        match songs.get_mut(&key) {
+            Some(saved_stats) => saved_stats.stats += stats,
+            None => {
+                let saved_stats = SavedStats {
+                    // listens doesn't have path, so it is only used to identify the song in
+                    // messages
+                    path: format!("{} - {}", listen.artist, listen.title),
+                    hash: None,
+                    title: Some(listen.title),
+                    artist: Some(listen.artist),
+                    album: listen.album,
+                    track_id: None,
+                    stats,
+                };
+                songs.insert(key, saved_stats);
             }
-            None
         }
-        ImportMethod::Title(_) => todo!(),
-        ImportMethod::Hash(_) => todo!(),
-        ImportMethod::TrackId(_) => todo!(),
     }
+    songs.into_values().collect()
 }
 
 /// import method for arguments
 #[derive(Debug, Clone, ValueEnum)]
 pub enum ImportMethodConfig {
-    /// imports using hashes as key, songs need to have the same name as exported ones. but it supports only for tags not for stickers
+    /// imports using hash of the audio content as key, stats should be exported with --hash. requires access to music directory
     Hash,
     /// imports stats using base filename as key
     File,
-    /// imports stats by taking trackid as key
+    /// imports stats by taking musicbrainz trackid as key, stats should be exported with tags
     TrackId,
-    /// imports stats by taking title as key
+    /// imports stats by taking title as key, artist and album are matched if they are exported
     Title,
     /// imports by taking path from mpd_root directory as key
     Path,
 }
 
+/// parses prefix rewrite given as `<from>=<to>`
+fn parse_rewrite(rewrite: &str) -> Result<(String, String), String> {
+    rewrite
+        .split_once('=')
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .ok_or_else(|| "expected <from>=<to>".to_string())
+}
+
+/// Configuration Options for Import
+#[derive(Args, Debug)]
+pub struct ImportConfig {
+    /// strategy to import songs
+    #[arg(value_enum, short='M', long, default_value_t=ImportMethodConfig::Path)]
+    method: ImportMethodConfig,
+    /// import stats and if there is already stats available then add both
+    #[arg(short, long)]
+    merge: bool,
+    /// import listens exported from the service as play counts, songs are matched by title,
+    /// artist and album. Play counts are always added to the existing stats
+    #[arg(value_enum, short, long)]
+    from: Option<ListenSource>,
+    /// import play counts, skip counts and ratings from the library file of the player, songs are
+    /// matched by path relative to mpd's music directory
+    #[arg(
+        value_enum,
+        short,
+        long,
+        conflicts_with("from"),
+        requires("input_file")
+    )]
+    player: Option<Player>,
+    /// replaces the prefix of the paths in the player's library, `<from>=<to>`. If no prefix
+    /// matches, then mpd's music directory is removed from the path if it is known
+    #[arg(long, value_parser = parse_rewrite, requires("player"))]
+    rewrite_prefix: Vec<(String, String)>,
+    /// file containing stats, if not present then reads it from stdin
+    #[arg()]
+    input_file: Option<PathBuf>,
+}
+
+/// returns the path relative to mpd's music directory, first matching prefix of `rewrites` is
+/// replaced, if none matches then music directory is removed. Prefixes are matched by whole
+/// components of the path
+fn rewrite_path(path: &path::Path, rewrites: &[(String, String)]) -> PathBuf {
+    if let Some((rest, to)) = rewrites
+        .iter()
+        .find_map(|(from, to)| Some((path.strip_prefix(from).ok()?, to)))
+    {
+        return path::Path::new(to).join(rest);
+    }
+    ROOT_DIR
+        .get()
+        .and_then(|root| path.strip_prefix(root).ok())
+        .unwrap_or(path)
+        .to_path_buf()
+}
+
+/// converts the songs of the player's library to stats with path relative to mpd's music
+/// directory. mpd's paths are always utf-8, so songs whose path isn't valid utf-8 can't be
+/// matched and are returned separately
+fn player_to_stats(
+    songs: Vec<players::PlayerSong>,
+    rewrites: &[(String, String)],
+) -> (Vec<SavedStats>, Vec<String>) {
+    let mut stats = Vec::with_capacity(songs.len());
+    let mut invalid = Vec::new();
+    for song in songs {
+        let path = rewrite_path(&song.path, rewrites);
+        let Some(path) = path.to_str() else {
+            warn!("skipping {:?}, path is not valid utf-8", path);
+            invalid.push(format!("{} (path is not valid utf-8)", path.display()));
+            continue;
+        };
+        stats.push(SavedStats {
+            path: path.to_owned(),
+            hash: None,
+            title: song.title,
+            artist: song.artist,
+            album: song.album,
+            track_id: None,
+            stats: song.stats,
+        });
+    }
+    (stats, invalid)
+}
+
 /// imports stats from a given file
+/// `from` imports listens exported from other services as play counts, they are always matched
+/// by title, artist and album
+/// `player` imports stats from the library of the player, they are always matched by path
 pub fn import_stats(
     client: &mut mpd::Client<ConnType>,
-    import_method_config: ImportMethodConfig,
-    input_file: Option<PathBuf>,
-    merge: bool,
-    use_tags: bool,
+    config: &ImportConfig,
+    store: &mut dyn StatsStore,
     mut confirm_all: bool,
 ) {
-    let mut reader: Vec<SavedStats> = if let Some(input_file_path) = input_file {
-        debug!("reading from file {:?}", input_file_path);
-        let f = std::fs::File::open(input_file_path).unwrap();
-        serde_json::from_reader(f).unwrap()
+    // listens are only plays, so replacing would lose the skips and ratings of the song
+    let merge = config.merge || config.from.is_some();
+    let mut unmatched = Vec::new();
+    let (mut reader, import_method_config): (Vec<SavedStats>, _) =
+        match (config.player, &config.input_file) {
+            (Some(player), Some(library)) => {
+                let songs =
+                    players::read_library(player, library).try_unwrap("Couldn't read the library");
+                info!("found {} songs in the library", songs.len());
+                let (stats, invalid) = player_to_stats(songs, &config.rewrite_prefix);
+                unmatched.extend(invalid);
+                (stats, ImportMethodConfig::Path)
+            }
+            (_, input_file) => {
+                let input: Box<dyn Read> = if let Some(input_file_path) = input_file {
+                    debug!("reading from file {:?}", input_file_path);
+                    Box::new(std::fs::File::open(input_file_path).unwrap())
+                } else {
+                    debug!("reading from stdin");
+                    Box::new(std::io::stdin())
+                };
+                match config.from {
+                    Some(source) => {
+                        let listens = scrobble::read_listens(source, input)
+                            .try_unwrap("Couldn't read the listens");
+                        info!("found {} listens", listens.len());
+                        (listens_to_stats(listens), ImportMethodConfig::Title)
+                    }
+                    None => (
+                        serde_json::from_reader(input).unwrap(),
+                        config.method.clone(),
+                    ),
+                }
+            }
+        };
+    info!("found {} elements", reader.len());
+    let mut imported = 0;
+    let song_list = match import_method_config {
+        // tags are required for matching
+        ImportMethodConfig::Title | ImportMethodConfig::TrackId => client.listallinfo(),
+        _ => client.listall(),
+    }
+    .try_unwrap("Couldn't get the songs from mpd");
+    let hashes = if let ImportMethodConfig::Hash = import_method_config {
+        require_root_dir();
+        hash_songs(&song_list)
     } else {
-        debug!("reading from stdin");
-        serde_json::from_reader(std::io::stdin()).unwrap()
+        HashMap::new()
     };
-    info!("found {} elements", reader.len());
-    let song_list = client.listall().unwrap();
-    // TODO: clean this dirt
-    // if merge is set add present and new value
-    reader.iter_mut().for_each(|saved_stats|{
-        let import_meth = match import_method_config{
+    // entries matching the same song are added, so that one doesn't overwrite the other
+    let mut matched: Vec<(String, Statistics)> = Vec::new();
+    let mut matched_idx: HashMap<String, usize> = HashMap::new();
+    for saved_stats in reader.iter_mut() {
+        let import_meth = match import_method_config {
             ImportMethodConfig::File => ImportMethod::FileName(&saved_stats.path),
-            ImportMethodConfig::Hash | ImportMethodConfig::Title | ImportMethodConfig::TrackId => todo!(),
-            ImportMethodConfig::Path => ImportMethod::FullPath(&saved_stats.path),
-        };
-        info!("importing stats {:?} to {}", saved_stats.stats, saved_stats.path);
-        if let Some(found_song) =get_song_by_key(&import_meth, &song_list){
-            let relative_path = &found_song.file;
-            if use_tags{
-                let mut full_path = path::PathBuf::from(ROOT_DIR.get().expect("statistics to tag requires full path, try to use --socket-file or set root-dir manually"));
-                full_path.push(relative_path);
-                debug!("Full path {:?}", full_path);
-                if full_path.is_file(){
-                    if merge{
-                        if let Ok(old_stats) = stats_from_tag(&full_path){
-                            debug!("adding old stats {:?}", old_stats);
-                            saved_stats.stats+= old_stats;
-                        }else{
-                            debug!("no old stats for {:?}", full_path);
-                        };
-                    }
-                    // if confirm all is set then no need to check else ask for user confirmation
-                    if!confirm_all{
-                        print!("import {full_path:?} - {:?}, Confirm: Y(all)/y(this)/[n](no)", saved_stats.stats);
-                        if !confirm_user(&mut confirm_all){
-                            return
-                        }
-                    }
-                    stats_to_tag(&full_path, &saved_stats.stats).unwrap_or_else(|err| warn!("failed to write stats to {:?}, due to : {:?}", full_path, err));
-                }else{
-                    warn!("skipping {}: No such file or directory", saved_stats.path);
+            ImportMethodConfig::Hash => match &saved_stats.hash {
+                Some(hash) => ImportMethod::Hash(hash),
+                None => {
+                    warn!("skipping {}, it is exported without hash", saved_stats.path);
+                    continue;
                 }
-            }else{
-                if merge{
-                    if let Ok(old_stats) = stats_from_sticker(client, &path::PathBuf::from(&saved_stats.path)){
-                        debug!("adding old stats {:?}", old_stats);
-                        saved_stats.stats+=old_stats;
-                    }else{
-                        debug!("no old stats for {:?}", saved_stats.path);
-                    };
+            },
+            ImportMethodConfig::Title => match &saved_stats.title {
+                Some(title) => ImportMethod::Title {
+                    title,
+                    artist: saved_stats.artist.as_deref(),
+                    album: saved_stats.album.as_deref(),
+                },
+                None => {
+                    warn!(
+                        "skipping {}, it is exported without title",
+                        saved_stats.path
+                    );
+                    continue;
                 }
-                // if confirm all is set then no need to check else ask for user confirmation
-                if!confirm_all{
-                    print!("import {} - {:?}, Confirm Y(all)/y(this)/[n](no):", saved_stats.path, saved_stats.stats);
-                    if !confirm_user(&mut confirm_all){
-                        return
-                    }
+            },
+            ImportMethodConfig::TrackId => match &saved_stats.track_id {
+                Some(track_id) => ImportMethod::TrackId(track_id),
+                None => {
+                    warn!(
+                        "skipping {}, it is exported without track id",
+                        saved_stats.path
+                    );
+                    continue;
                 }
-                stats_to_sticker(client, &path::PathBuf::from(relative_path), &saved_stats.stats).unwrap_or_else(|err| warn!("failed update sticker with stats to {:?}, due to : {:?}", saved_stats.path, err));
+            },
+            ImportMethodConfig::Path => ImportMethod::FullPath(&saved_stats.path),
+        };
+        info!(
+            "importing stats {:?} to {}",
+            saved_stats.stats, saved_stats.path
+        );
+        let found_song = match get_song_by_key(&import_meth, &song_list, &hashes)[..] {
+            [found_song] => found_song,
+            [] => {
+                warn!(
+                    "Failed to find the song \"{}\" for importing",
+                    saved_stats.path
+                );
+                unmatched.push(format!("{} (not found)", saved_stats.path));
+                continue;
+            }
+            ref candidates => {
+                warn!(
+                    "skipping \"{}\", it matches multiple songs: {:?}",
+                    saved_stats.path,
+                    candidates
+                        .iter()
+                        .map(|song| song.file.as_str())
+                        .collect::<Vec<_>>()
+                );
+                unmatched.push(format!("{} (matches multiple songs)", saved_stats.path));
+                continue;
+            }
+        };
+        match matched_idx.get(&found_song.file) {
+            Some(&idx) => {
+                debug!(
+                    "adding stats of \"{}\" to {}",
+                    saved_stats.path, found_song.file
+                );
+                matched[idx].1 += std::mem::take(&mut saved_stats.stats);
+            }
+            None => {
+                matched_idx.insert(found_song.file.clone(), matched.len());
+                matched.push((
+                    found_song.file.clone(),
+                    std::mem::take(&mut saved_stats.stats),
+                ));
             }
-        }else{
-            warn!("Failed to find the song \"{}\" for importing", saved_stats.path);
         }
-    });
+    }
+    // if merge is set add present and new value
+    for (file, mut stats) in matched {
+        let relative_path = path::PathBuf::from(&file);
+        if merge {
+            if let Ok(old_stats) = store.read(client, &relative_path) {
+                debug!("adding old stats {:?}", old_stats);
+                stats += old_stats;
+            } else {
+                debug!("no old stats for {:?}", relative_path);
+            };
+        }
+        // if confirm all is set then no need to check else ask for user confirmation
+        if !confirm_all {
+            print!(
+                "import {file} - {:?}, Confirm Y(all)/y(this)/[n](no):",
+                stats
+            );
+            if !confirm_user(&mut confirm_all) {
+                continue;
+            }
+        }
+        match store.write(client, &relative_path, &stats) {
+            Ok(_) => imported += 1,
+            Err(err) => warn!(
+                "failed to write stats to {:?}, due to : {:?}",
+                relative_path, err
+            ),
+        }
+    }
+    println!("imported stats of {imported} songs");
+    if !unmatched.is_empty() {
+        println!("{} entries couldn't be matched:", unmatched.len());
+        for entry in unmatched {
+            println!("\t{entry}");
+        }
+    }
 }
 
 /// exports all stats to a file
 pub fn export_stats(
     client: &mut mpd::Client<ConnType>,
     output_file: Option<PathBuf>,
-    _enable_hash: bool,
-    use_tags: bool,
+    enable_hash: bool,
+    format: OutputFormat,
+    rating: &RatingConfig,
+    store: &mut dyn StatsStore,
 ) {
-    let mut json_stats = Vec::new();
-    client.listall().unwrap().iter().filter_map(|song| {
-        if use_tags{
-            let mut pth = path::PathBuf::from(ROOT_DIR.get().expect("statistics to tag requires full path, try to use --socket-file or set root-dir manually"));
-            pth.push(&song.file);
-            match stats_from_tag(&pth){
-                Ok(stats) => {
-                    info!("exporting {:?}: {:?}", pth, stats);
-                    Some((song, stats))
-
-                },
-                Err(Error::Id3ReadTag) => {
-                    warn!("skipping {:?}", &pth);
-                    None
-                },
-                Err(_)=> panic!("Failed to get stats for {:?}", &pth),
+    if enable_hash {
+        require_root_dir();
+    }
+    let all_stats = store.list(client).try_unwrap("Failed to get the stats");
+    let songs_info = library_info(client);
+    let mut progress =
+        enable_hash.then(|| pbr::ProgressBar::on(std::io::stderr(), all_stats.len() as u64));
+    let json_stats: Vec<_> = all_stats
+        .into_iter()
+        .map(|(song, stats)| {
+            info!("exporting {:?}: {:?}", song, stats);
+            let hash = progress.as_mut().and_then(|progress| {
+                progress.inc();
+                audio_hash(&song)
+                    .map_err(|err| warn!("couldn't hash {:?}, due to {:?}", song, err))
+                    .ok()
+            });
+            let path = song
+                .to_str()
+                .expect("Failed to get the song name into string")
+                .to_owned();
+            let info = songs_info.get(&path);
+            SavedStats {
+                hash,
+                ..SavedStats::new(path, info, stats)
             }
-        }else{
-            stats_from_sticker(client, &path::PathBuf::from(&song.file)).ok().map(|stats| (song, stats))
-        }
-    }).for_each(|(song, stats)|{
-        json_stats.push(SavedStats{
-            path: song.file.clone(),
-            hash: None,
-            stats,
         })
-    });
+        .collect();
+    if let Some(mut progress) = progress {
+        progress.finish_print("hashing done");
+    }
     info!("Found {} stats", json_stats.len());
+    let row = |saved: &SavedStats| StatsRow::new(saved, rating);
     if let Some(output_file) = output_file {
         info!("Writing stats to file {:?}", output_file);
         let f = std::fs::File::create(output_file).unwrap();
-        serde_json::to_writer(f, &json_stats).unwrap();
+        output::write_items(f, format, &json_stats, row)
     } else {
-        serde_json::to_writer(std::io::stdout(), &json_stats).unwrap();
+        output::write_items(std::io::stdout(), format, &json_stats, row)
     }
+    .try_unwrap("Couldn't export the stats");
 }
-
 /// returns true if user confirms else false if cancel is requested
 /// if user requests Y(confirm all) then confirm_all will be set to true
 fn confirm_user(confirm_all: &mut bool) -> bool {
@@ -722,28 +1699,192 @@ fn confirm_user(confirm_all: &mut bool) -> bool {
 }
 
 /// clears stats of all files
-pub fn clear_stats(client: &mut mpd::Client<ConnType>, use_tags: bool, mut confirm_all: bool) {
-    let stat = Statistics::default();
-    client.listall().unwrap().iter().for_each(|song| {
-        if use_tags{
-            let mut pth = path::PathBuf::from(ROOT_DIR.get().expect("statistics to tag requires full path, try to use --socket-file or set root-dir manually"));
-            pth.push(&song.file);
-            if!confirm_all{
-                print!("Stats of {pth:?} will be reset to {stat:?}, Confirm: Y(all)/y(this)/[n](no)");
-                if !confirm_user(&mut confirm_all){
-                    return
-                }
+pub fn clear_stats(
+    client: &mut mpd::Client<ConnType>,
+    store: &mut dyn StatsStore,
+    mut confirm_all: bool,
+) {
+    for (song, stat) in store.list(client).try_unwrap("Failed to get the stats") {
+        debug!("resetting stats for {:?}", song);
+        if !confirm_all {
+            print!("Stats {stat:?} of {song:?} will be reset, Confirm Y(all)/y(this)/[n](no):");
+            if !confirm_user(&mut confirm_all) {
+                continue;
             }
-            stats_to_tag(&pth,&stat).unwrap_or_else(|err| warn!("failed to reset stats of {}, due to {:?}", song.file, err));
-        }else{
-            debug!("resetting sticker stats for {}", song.file);
-            if!confirm_all{
-                print!("Stats of {} will be reset to {stat:?}, Confirm Y(all)/y(this)/[n](no):", song.file);
-                if !confirm_user(&mut confirm_all){
-                    return
-                }
+        }
+        store
+            .delete(client, &song)
+            .unwrap_or_else(|err| warn!("failed to reset stats of {:?}, due to {:?}", song, err));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// returns the time at the middle of the month
+    fn mid_month(year: i32, month: u32) -> DateTime<Utc> {
+        NaiveDate::from_ymd_opt(year, month, 16)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .unwrap()
+            .and_utc()
+    }
+
+    /// returns period stats of the month
+    fn period(year: i32, month: u32, play_cnt: u32, skip_cnt: u32) -> PeriodStats {
+        PeriodStats {
+            month: NaiveDate::from_ymd_opt(year, month, 1).unwrap(),
+            play_cnt,
+            skip_cnt,
+        }
+    }
+
+    #[test]
+    fn decayed_counts_halve_every_half_life() {
+        let stats = Statistics {
+            play_cnt: 4,
+            skip_cnt: 2,
+            periods: vec![period(2027, 1, 4, 2)],
+            ..Default::default()
+        };
+        let now = mid_month(2027, 1) + chrono::Duration::days(30);
+        let (play, skip) = stats.decayed_counts(30.0, now);
+        assert!((play - 2.0).abs() < 1e-3, "{play}");
+        assert!((skip - 1.0).abs() < 1e-3, "{skip}");
+    }
+
+    #[test]
+    fn undated_counts_are_aged_from_last_time() {
+        let now = mid_month(2028, 1);
+        let weight = |time: DateTime<Utc>| {
+            0.5f32.powf((now - time).num_seconds() as f32 / SECS_PER_DAY / 180.0)
+        };
+        let old = Statistics {
+            play_cnt: 10,
+            skip_cnt: 2,
+            last_played: Some(mid_month(2026, 1)),
+            last_skipped: Some(mid_month(2027, 1)),
+            ..Default::default()
+        };
+        let (play, skip) = old.decayed_counts(180.0, now);
+        assert!(
+            (play - 10.0 * weight(mid_month(2027, 1))).abs() < 1e-3,
+            "{play}"
+        );
+        assert!(
+            (skip - 2.0 * weight(mid_month(2027, 1))).abs() < 1e-3,
+            "{skip}"
+        );
+        // undated counts are older than the periods
+        let mut played = old.clone();
+        played.play_cnt += 1;
+        played.last_played = Some(now);
+        played.periods = vec![period(2026, 6, 1, 0)];
+        let (play, _) = played.decayed_counts(180.0, now);
+        let first_period = period(2026, 6, 0, 0).month.and_hms_opt(0, 0, 0).unwrap();
+        let expected = 10.0 * weight(first_period.and_utc()) + weight(mid_month(2026, 6));
+        assert!((play - expected).abs() < 1e-3, "{play}");
+        // counts without any time are not decayed
+        let untimed = Statistics {
+            play_cnt: 10,
+            ..Default::default()
+        };
+        assert_eq!(untimed.decayed_counts(180.0, now), (10.0, 0.0));
+    }
+
+    #[test]
+    fn first_seen_is_not_set_for_older_counts() {
+        let mut new = Statistics::default();
+        new.played();
+        assert!(new.first_seen.is_some());
+        let mut old = Statistics {
+            play_cnt: 3,
+            ..Default::default()
+        };
+        old.skipped();
+        assert_eq!(old.first_seen, None);
+        assert_eq!(old.skip_cnt, 1);
+        assert_eq!(old.periods.len(), 1);
+    }
+
+    #[test]
+    fn ratings_are_bounded() {
+        let config = RatingConfig {
+            algorithm: RatingAlgorithm::Wilson,
+            half_life: 180.0,
+        };
+        for (play_cnt, skip_cnt) in [(0, 0), (1, 0), (0, 1), (100, 3), (3, 100)] {
+            let stats = Statistics {
+                play_cnt,
+                skip_cnt,
+                ..Default::default()
+            };
+            for algorithm in [
+                RatingAlgorithm::Ratio,
+                RatingAlgorithm::Wilson,
+                RatingAlgorithm::Bayesian,
+                RatingAlgorithm::Decayed,
+            ] {
+                let config = RatingConfig {
+                    algorithm,
+                    half_life: 180.0,
+                };
+                let rating = stats.computed_rating(&config);
+                assert!((0.0..=10.0).contains(&rating), "{algorithm:?} {rating}");
             }
-            stats_to_sticker(client,&path::PathBuf::from(&song.file),&stat).unwrap_or_else(|err| warn!("failed to reset stats of {}, due to {:?}", song.file, err));
         }
-    });
+        let stats = Statistics {
+            user_rating: Some(7.0),
+            ..Default::default()
+        };
+        assert_eq!(stats.get_ratings(&config), 7.0);
+    }
+
+    #[test]
+    fn rewrite_prefix_of_player_paths() {
+        let rewrites = [
+            ("/home/user/Music".to_string(), "".to_string()),
+            ("/mnt/music/".to_string(), "nas".to_string()),
+        ];
+        let rewrite = |path: &str| rewrite_path(path::Path::new(path), &rewrites);
+        assert_eq!(
+            rewrite("/home/user/Music/a/b.flac"),
+            PathBuf::from("a/b.flac")
+        );
+        assert_eq!(rewrite("/mnt/music/c.ogg"), PathBuf::from("nas/c.ogg"));
+        // prefixes match whole components
+        assert_eq!(
+            rewrite("/home/user/Music2/d.mp3"),
+            PathBuf::from("/home/user/Music2/d.mp3")
+        );
+    }
+
+    #[test]
+    fn non_utf8_player_paths_are_unmatched() {
+        use std::os::unix::ffi::OsStrExt;
+        let song = |path: &[u8]| players::PlayerSong {
+            path: PathBuf::from(std::ffi::OsStr::from_bytes(path)),
+            title: None,
+            artist: None,
+            album: None,
+            stats: Statistics::default(),
+        };
+        let rewrites = [("/music".to_string(), "".to_string())];
+        let (stats, invalid) = player_to_stats(
+            vec![song(b"/music/caf\xc3\xa9.mp3"), song(b"/music/caf\xe9.mp3")],
+            &rewrites,
+        );
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].path, "caf\u{e9}.mp3");
+        assert_eq!(invalid.len(), 1);
+    }
+
+    #[test]
+    fn half_life_is_positive() {
+        assert_eq!(parse_half_life("30"), Ok(30.0));
+        assert_eq!(parse_half_life("0.5"), Ok(0.5));
+        for invalid in ["0", "-1", "NaN", "inf", "days"] {
+            assert!(parse_half_life(invalid).is_err(), "{invalid}");
+        }
+    }
 }
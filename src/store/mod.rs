@@ -0,0 +1,114 @@
+//! This module abstracts the place where statistics of the songs are stored.
+//! Every storage backend implements [`StatsStore`], so commands and listener doesn't have to know
+//! which backend is in use.
+mod sqlite;
+mod sticker;
+mod tag;
+
+use crate::{error::Error, stats::Statistics, ConnType};
+use clap::{Args, ValueEnum};
+use std::path::{Path, PathBuf};
+
+pub use sqlite::SqliteStore;
+pub use sticker::StickerStore;
+pub use tag::{audio_hash, TagStore};
+
+/// storage backends which can be selected for storing statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StoreKind {
+    /// mpd's sticker database, stats will be lost if the songs are moved
+    Sticker,
+    /// metadata of the song file itself, persistent across file moves
+    Tags,
+    /// local sqlite database, doesn't modify the song files and doesn't require music directory
+    Sqlite,
+}
+
+impl StoreKind {
+    /// returns true if backend requires full path of the song, i.e. mpd's music directory
+    pub fn needs_root_dir(&self) -> bool {
+        matches!(self, StoreKind::Tags)
+    }
+}
+
+/// Configuration options for storage backends
+#[derive(Args, Debug)]
+pub struct StoreConfig {
+    /// use eyed3 tags to store ratings, shorthand for `--store tags`.
+    #[arg(short = 't', long, env = "MSCOUT_USE_TAGS", conflicts_with = "store")]
+    use_tags: bool,
+    /// where to store the ratings. tags are persistante across file moves, where as incase of mpd sticker these will be erased if you move the files.
+    #[arg(id = "store", value_enum, short, long = "store", env = "MSCOUT_STORE", default_value_t = StoreKind::Sticker)]
+    kind: StoreKind,
+    /// path to sqlite database, used only with `--store sqlite`
+    #[arg(long, env = "MSCOUT_DB", default_value_t=format!("{}/.local/share/mscout/stats.db", std::env::var("HOME").unwrap_or_else(|_|".".to_string())), value_hint(clap::ValueHint::FilePath))]
+    db_path: String,
+    /// store hash of the audio content in sqlite database, so that stats of a moved song are found
+    /// by its content. Hashing reads the whole song, so it requires music directory and is slow.
+    /// used only with `--store sqlite`
+    #[arg(long, env = "MSCOUT_TRACK_MOVES")]
+    track_moves: bool,
+    /// along with the comment, also write standard id3 popularimeter(POPM) and play counter(PCNT)
+    /// frames, so that other players can show ratings and play count. used only with tags.
+    #[arg(long, env = "MSCOUT_ID3_FRAMES")]
+    id3_frames: bool,
+}
+
+impl StoreConfig {
+    /// returns the selected storage backend
+    pub fn kind(&self) -> StoreKind {
+        if self.use_tags {
+            StoreKind::Tags
+        } else {
+            self.kind
+        }
+    }
+
+    /// returns true if the selected backend requires mpd's music directory
+    pub fn needs_root_dir(&self) -> bool {
+        let kind = self.kind();
+        kind.needs_root_dir() || (kind == StoreKind::Sqlite && self.track_moves)
+    }
+
+    /// creates the selected backend
+    pub fn open(&self) -> Result<Box<dyn StatsStore>, Error> {
+        Ok(match self.kind() {
+            StoreKind::Sticker => Box::new(StickerStore),
+            StoreKind::Tags => Box::new(TagStore::new(self.id3_frames)),
+            StoreKind::Sqlite => Box::new(SqliteStore::open(
+                Path::new(&self.db_path),
+                self.track_moves,
+            )?),
+        })
+    }
+}
+
+/// Operations required by a storage backend.
+/// `song` is always path of the song relative to mpd's music directory, unless stated otherwise
+/// by the backend.
+pub trait StatsStore {
+    /// reads the statistics of the song, if the song doesn't have any stats then default stats are
+    /// returned.
+    fn read(
+        &mut self,
+        client: &mut mpd::Client<ConnType>,
+        song: &Path,
+    ) -> Result<Statistics, Error>;
+
+    /// writes the statistics to the song, overwriting existing ones.
+    fn write(
+        &mut self,
+        client: &mut mpd::Client<ConnType>,
+        song: &Path,
+        stats: &Statistics,
+    ) -> Result<(), Error>;
+
+    /// removes the statistics of the song from the store.
+    fn delete(&mut self, client: &mut mpd::Client<ConnType>, song: &Path) -> Result<(), Error>;
+
+    /// lists all the songs which has statistics in this store.
+    fn list(
+        &mut self,
+        client: &mut mpd::Client<ConnType>,
+    ) -> Result<Vec<(PathBuf, Statistics)>, Error>;
+}
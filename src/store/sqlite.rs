@@ -0,0 +1,258 @@
+//! stores statistics in a local sqlite database
+use super::{audio_hash, StatsStore};
+use crate::{error::Error, stats::Statistics, ConnType, ROOT_DIR};
+use log::{debug, error, info, trace, warn};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+/// sqlite backend, stats are stored as json keyed by song path relative to mpd's directory.
+/// This doesn't require access to the song files, so it works with remote mpd as well.
+/// If moves are tracked and music directory is accessible then hash of the audio content is stored
+/// along with the path, so that stats are found even after the song is moved.
+#[derive(Debug)]
+pub struct SqliteStore {
+    /// connection to the database
+    conn: Connection,
+    /// whether to hash the songs to find the stats of moved songs
+    track_moves: bool,
+}
+
+/// logs the database error and converts it to crate error
+fn db_error(err: rusqlite::Error) -> Error {
+    error!("database error: {err}");
+    Error::Database
+}
+
+/// returns modification time of the song in nanoseconds, if music directory is accessible
+fn modified_time(spath: &Path) -> Option<i64> {
+    let modified = std::fs::metadata(ROOT_DIR.get()?.join(spath))
+        .and_then(|meta| meta.modified())
+        .map_err(|err| debug!("couldn't get modified time of {:?}, due to {err}", spath))
+        .ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos() as i64)
+}
+
+impl SqliteStore {
+    /// opens the database at `db_path`, creates the database and tables if doesn't exists
+    pub fn open(db_path: &Path, track_moves: bool) -> Result<Self, Error> {
+        debug!("opening stats database {:?}", db_path);
+        if let Some(parent) = db_path.parent() {
+            std::fs::create_dir_all(parent).map_err(|err| {
+                error!("couldn't create directory {:?} due to {err}", parent);
+                Error::Database
+            })?;
+        }
+        let conn = Connection::open(db_path).map_err(db_error)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS stats (
+                path TEXT PRIMARY KEY NOT NULL,
+                hash TEXT,
+                stats TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS stats_hash ON stats(hash);
+            CREATE TABLE IF NOT EXISTS hashes (
+                path TEXT PRIMARY KEY NOT NULL,
+                mtime INTEGER NOT NULL,
+                hash TEXT
+            );",
+        )
+        .map_err(db_error)?;
+        if track_moves && ROOT_DIR.get().is_none() {
+            warn!("music directory is not known, moved songs can't be tracked");
+        }
+        Ok(Self { conn, track_moves })
+    }
+
+    /// returns audio hash of the song if music directory is accessible. Hashing reads the whole
+    /// song, so hashes are cached until the song is modified.
+    fn song_hash(&mut self, spath: &Path) -> Result<Option<String>, Error> {
+        let Some(mtime) = modified_time(spath) else {
+            return Ok(None);
+        };
+        let path = spath.to_str().unwrap();
+        let cached: Option<Option<String>> = self
+            .conn
+            .query_row(
+                "SELECT hash FROM hashes WHERE path = ?1 AND mtime = ?2",
+                params![path, mtime],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error)?;
+        if let Some(hash) = cached {
+            trace!("using cached hash of {:?}", spath);
+            return Ok(hash);
+        }
+        // songs which can't be hashed are cached as well, so that they aren't read again
+        let hash = audio_hash(spath)
+            .map_err(|err| debug!("couldn't hash {:?}, due to {:?}", spath, err))
+            .ok();
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO hashes (path, mtime, hash) VALUES (?1, ?2, ?3)",
+                params![path, mtime, hash],
+            )
+            .map_err(db_error)?;
+        Ok(hash)
+    }
+
+    /// looks up the song by hash of its audio content, if found and the old path of the song
+    /// doesn't exist anymore then the song's path is updated. Copies of the same song are not
+    /// moves, so they don't share the stats.
+    fn find_moved(&mut self, spath: &Path) -> Result<Option<String>, Error> {
+        if !self.track_moves {
+            return Ok(None);
+        }
+        let Some(root_dir) = ROOT_DIR.get() else {
+            return Ok(None);
+        };
+        let Some(hash) = self.song_hash(spath)? else {
+            return Ok(None);
+        };
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path, stats FROM stats WHERE hash = ?1")
+            .map_err(db_error)?;
+        let candidates = stmt
+            .query_map(params![hash], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(db_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_error)?;
+        drop(stmt);
+        let moved = candidates.into_iter().find(|(old_path, _)| {
+            let exists = root_dir.join(old_path).exists();
+            if exists {
+                debug!("{:?} is a copy of {old_path}, not moving its stats", spath);
+            }
+            !exists
+        });
+        let Some((old_path, stats)) = moved else {
+            return Ok(None);
+        };
+        info!("song {old_path} is moved to {:?}", spath);
+        self.conn
+            .execute(
+                "UPDATE stats SET path = ?1 WHERE path = ?2",
+                params![spath.to_str().unwrap(), old_path],
+            )
+            .map_err(db_error)?;
+        Ok(Some(stats))
+    }
+}
+
+impl StatsStore for SqliteStore {
+    /// gets the stats from database, if the song is not present then it is looked up by hash, if
+    /// not found then returns 0,0
+    fn read(
+        &mut self,
+        client: &mut mpd::Client<ConnType>,
+        spath: &Path,
+    ) -> Result<Statistics, Error> {
+        trace!("getting stats from database for {:?}", spath);
+        let mut stats: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT stats FROM stats WHERE path = ?1",
+                params![spath.to_str().unwrap()],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error)?;
+        if stats.is_none() {
+            stats = self.find_moved(spath)?;
+        }
+        Ok(stats.map_or_else(Statistics::default, |stats| {
+            serde_json::from_str(&stats).unwrap_or_else(|err| {
+                warn!("couldn't parse stats from database: {:?}", err);
+                // if the stats are invalid then remove it.
+                self.delete(client, spath)
+                    .unwrap_or_else(|err| warn!("failed to delete stats {:?}", err));
+                Statistics::default()
+            })
+        }))
+    }
+
+    /// set the stats to database.
+    fn write(
+        &mut self,
+        _client: &mut mpd::Client<ConnType>,
+        spath: &Path,
+        stats: &Statistics,
+    ) -> Result<(), Error> {
+        info!("setting stats {:?} to database for {:?}", stats, spath);
+        // hash of the song is stored only once, later writes keep it
+        let hash = if self.track_moves {
+            let stored: Option<Option<String>> = self
+                .conn
+                .query_row(
+                    "SELECT hash FROM stats WHERE path = ?1",
+                    params![spath.to_str().unwrap()],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(db_error)?;
+            match stored {
+                Some(Some(_)) => None,
+                _ => self.song_hash(spath)?,
+            }
+        } else {
+            None
+        };
+        self.conn
+            .execute(
+                "INSERT INTO stats (path, hash, stats) VALUES (?1, ?2, ?3)
+                ON CONFLICT(path) DO UPDATE SET
+                    hash = coalesce(excluded.hash, hash),
+                    stats = excluded.stats",
+                params![
+                    spath.to_str().unwrap(),
+                    hash,
+                    serde_json::to_string(stats).expect("Couldn't dump stats to json")
+                ],
+            )
+            .map_err(db_error)?;
+        Ok(())
+    }
+
+    /// removes the song from database
+    fn delete(&mut self, _client: &mut mpd::Client<ConnType>, spath: &Path) -> Result<(), Error> {
+        debug!("deleting stats of {:?} from database", spath);
+        self.conn
+            .execute(
+                "DELETE FROM stats WHERE path = ?1",
+                params![spath.to_str().unwrap()],
+            )
+            .map_err(db_error)?;
+        Ok(())
+    }
+
+    /// lists all the songs from database
+    fn list(
+        &mut self,
+        _client: &mut mpd::Client<ConnType>,
+    ) -> Result<Vec<(PathBuf, Statistics)>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT path, stats FROM stats")
+            .map_err(db_error)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(db_error)?;
+        let mut stats = Vec::new();
+        for row in rows {
+            let (path, stat) = row.map_err(db_error)?;
+            match serde_json::from_str(&stat) {
+                Ok(stat) => stats.push((PathBuf::from(path), stat)),
+                Err(err) => warn!("skipping {path}, couldn't parse stats: {:?}", err),
+            }
+        }
+        Ok(stats)
+    }
+}
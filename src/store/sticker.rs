@@ -0,0 +1,95 @@
+//! stores statistics in mpd's sticker database
+use super::StatsStore;
+use crate::{error::Error, stats::Statistics, ConnType, MP_DESC};
+use log::{debug, error, info, trace, warn};
+use std::path::{Path, PathBuf};
+
+/// mpd sticker database backend, stats are stored as json in `msout` sticker of the song.
+#[derive(Debug, Default)]
+pub struct StickerStore;
+
+impl StatsStore for StickerStore {
+    /// gets the stats from mpd sticker database.
+    fn read(
+        &mut self,
+        client: &mut mpd::Client<ConnType>,
+        spath: &Path,
+    ) -> Result<Statistics, Error> {
+        trace!("getting stats from  mpd database for {:?}", spath);
+        // get the stats from sticker, if not found then return 0,0
+        client
+            .sticker("song", spath.to_str().unwrap(), MP_DESC)
+            .map_or_else(
+                |err| {
+                    debug!("error {err} while getting stats");
+                    match err {
+                        mpd::error::Error::Parse(_) => Ok(Statistics::default()),
+                        mpd::error::Error::Server(_) => Err(Error::FileNotExists),
+                        _ => Err(Error::ConnectionFailed),
+                    }
+                },
+                |sticker| {
+                    Ok(serde_json::from_str(&sticker).unwrap_or_else(|err| {
+                        warn!("couldn't parse sticker: {:?}", err);
+                        // if the sticker is invalid then remove it.
+                        self.delete(client, spath)
+                            .unwrap_or_else(|err| warn!("failed to delete sticker {:?}", err));
+                        Statistics::default()
+                    }))
+                },
+            )
+    }
+
+    /// set the stats to mpd sticker database.
+    fn write(
+        &mut self,
+        client: &mut mpd::Client<ConnType>,
+        spath: &Path,
+        stats: &Statistics,
+    ) -> Result<(), Error> {
+        info!("setting stats {:?} to mpd database for {:?}", stats, spath);
+        client
+            .set_sticker(
+                "song",
+                spath.to_str().unwrap(),
+                MP_DESC,
+                &serde_json::to_string(stats).expect("Couldn't dump stats to json"),
+            )
+            .map_err(|err| {
+                error!("Couldn't dump to mpd  database due to {:?}", err);
+                Error::ConnectionFailed
+            })
+    }
+
+    /// removes `msout` sticker of the song
+    fn delete(&mut self, client: &mut mpd::Client<ConnType>, spath: &Path) -> Result<(), Error> {
+        debug!("deleting sticker of {:?}", spath);
+        client
+            .delete_sticker("song", spath.to_str().unwrap(), MP_DESC)
+            .map_err(|err| match err {
+                mpd::error::Error::Server(_) => Error::FileNotExists,
+                _ => Error::ConnectionFailed,
+            })
+    }
+
+    /// finds all songs with `msout` sticker from mpd's root directory
+    fn list(
+        &mut self,
+        client: &mut mpd::Client<ConnType>,
+    ) -> Result<Vec<(PathBuf, Statistics)>, Error> {
+        let stickers = client.find_sticker("song", "", MP_DESC).map_err(|err| {
+            error!("Couldn't list stickers due to {:?}", err);
+            Error::ConnectionFailed
+        })?;
+        Ok(stickers
+            .into_iter()
+            .filter_map(|(file, sticker)| match serde_json::from_str(&sticker) {
+                Ok(stats) => Some((PathBuf::from(file), stats)),
+                Err(err) => {
+                    warn!("skipping {file}, couldn't parse sticker: {:?}", err);
+                    None
+                }
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,145 @@
+//! computes hash of the audio content of the song. Tags are excluded from the hash, so that
+//! rewriting tags doesn't change the hash of the song.
+use super::{full_path, vorbis, Container};
+use crate::error::Error;
+use log::{debug, error};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// size of id3v1 tag at the end of the file
+const ID3V1_LEN: u64 = 128;
+/// size of apev2 tag footer
+const APE_FOOTER_LEN: u64 = 32;
+
+/// hashes `len` bytes from `start` of the file
+fn hash_range(
+    file: &mut std::fs::File,
+    start: u64,
+    len: u64,
+    hasher: &mut Sha256,
+) -> io::Result<()> {
+    file.seek(SeekFrom::Start(start))?;
+    io::copy(&mut io::BufReader::new(file.take(len)), hasher)?;
+    Ok(())
+}
+
+/// returns the size of tags appended at the end of mp3 file, i.e. id3v1 and apev2
+fn trailing_tags_len(file: &mut std::fs::File, file_len: u64, start: u64) -> io::Result<u64> {
+    let mut end = file_len;
+    if end >= start + ID3V1_LEN {
+        let mut magic = [0; 3];
+        file.seek(SeekFrom::Start(end - ID3V1_LEN))?;
+        file.read_exact(&mut magic)?;
+        if &magic == b"TAG" {
+            end -= ID3V1_LEN;
+        }
+    }
+    if end >= start + APE_FOOTER_LEN {
+        let mut footer = [0; APE_FOOTER_LEN as usize];
+        file.seek(SeekFrom::Start(end - APE_FOOTER_LEN))?;
+        file.read_exact(&mut footer)?;
+        if footer.starts_with(b"APETAGEX") {
+            // size includes footer but not header, header is present if the flag is set
+            let size = u32::from_le_bytes(footer[12..16].try_into().unwrap()) as u64;
+            let header = if footer[23] & 0x80 != 0 { 32 } else { 0 };
+            end = end.saturating_sub(size + header).max(start);
+        }
+    }
+    Ok(file_len - end)
+}
+
+/// hashes contents of all `mdat` atoms of mp4 file, metadata is stored in `moov` atom
+fn hash_mp4(file: &mut std::fs::File, file_len: u64, hasher: &mut Sha256) -> io::Result<()> {
+    let mut pos = 0;
+    while pos + 8 <= file_len {
+        let mut header = [0; 8];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut header)?;
+        let mut header_len = 8;
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            // atom extends to the end of file
+            0 => file_len - pos,
+            // 64 bit size follows the header
+            1 => {
+                let mut size = [0; 8];
+                file.read_exact(&mut size)?;
+                header_len += 8;
+                u64::from_be_bytes(size)
+            }
+            size => size as u64,
+        };
+        if size < header_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid atom size",
+            ));
+        }
+        if &header[4..] == b"mdat" {
+            hash_range(file, pos + header_len, size - header_len, hasher)?;
+        }
+        pos += size;
+    }
+    Ok(())
+}
+
+/// computes sha256 of the audio content of the song, returns hex encoded hash.
+/// `song` is either full path or relative to mpd's music directory
+pub fn audio_hash(song: &Path) -> Result<String, Error> {
+    let song_pbuff = full_path(song);
+    let song_path = song_pbuff.as_path();
+    let container = Container::detect(song_path)?;
+    let mut hasher = Sha256::new();
+    let io_error = |err: io::Error| {
+        error!("failed to hash {:?} due to {}", song_path, err);
+        Error::FileNotExists
+    };
+    match container {
+        Container::Ogg => {
+            // hash all the packets except comment header which is second packet of the stream
+            let mut seen_streams = HashSet::new();
+            let mut comment_found = HashSet::new();
+            for packet in vorbis::open_ogg(song_path)? {
+                let serial = packet.stream_serial();
+                if !seen_streams.insert(serial) && comment_found.insert(serial) {
+                    continue;
+                }
+                hasher.update(&packet.data);
+            }
+        }
+        Container::Flac | Container::Mp4 | Container::Id3 => {
+            let mut file = std::fs::File::open(song_path).map_err(io_error)?;
+            let file_len = file.metadata().map_err(io_error)?.len();
+            match container {
+                Container::Flac => {
+                    let start = vorbis::open_flac(song_path, false)?.metadata_len();
+                    hash_range(
+                        &mut file,
+                        start,
+                        file_len.saturating_sub(start),
+                        &mut hasher,
+                    )
+                }
+                Container::Mp4 => hash_mp4(&mut file, file_len, &mut hasher),
+                _ => {
+                    let mut header = Vec::with_capacity(10);
+                    (&mut file)
+                        .take(10)
+                        .read_to_end(&mut header)
+                        .map_err(io_error)?;
+                    let start = (vorbis::id3_prefix_len(&header) as u64).min(file_len);
+                    trailing_tags_len(&mut file, file_len, start).and_then(|trailing| {
+                        hash_range(&mut file, start, file_len - start - trailing, &mut hasher)
+                    })
+                }
+            }
+            .map_err(io_error)?;
+        }
+    }
+    let hash = format!("{:x}", hasher.finalize());
+    debug!("hash of {:?} is {}", song_path, hash);
+    Ok(hash)
+}
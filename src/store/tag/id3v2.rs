@@ -0,0 +1,282 @@
+//! reads and writes stats from id3 tags(from comments).
+//! Optionally standard popularimeter(POPM) and play counter(PCNT) frames are written as well, so
+//! that other players can use them.
+use super::parse_stats;
+use crate::{error::Error, stats::Statistics, MP_DESC};
+use id3::{
+    frame::{Comment, Content, Popularimeter, Unknown},
+    Frame, Tag, TagLike,
+};
+use log::{debug, error, info, warn};
+use std::path::Path;
+
+/// frame id of play counter
+const PLAY_COUNTER_ID: &str = "PCNT";
+/// frame id of popularimeter
+const POPULARIMETER_ID: &str = "POPM";
+
+/// converts stats to popularimeter rating which is in range 1-255, 0 means unknown.
+/// manual rating is used if the song is rated
+fn popularimeter_rating(stats: &Statistics) -> u8 {
+    if let Some(rating) = stats.user_rating {
+        return (1.0 + 254.0 * rating / 10.0).round() as u8;
+    }
+    let total = stats.play_cnt + stats.skip_cnt;
+    if total == 0 {
+        return 0;
+    }
+    (1.0 + 254.0 * stats.play_cnt as f32 / total as f32).round() as u8
+}
+
+/// converts popularimeter rating to manual rating in range 0-10, inverse of
+/// [`popularimeter_rating`]. 0 means the song is not rated
+fn rating_from_popularimeter(rating: u8) -> Option<f32> {
+    (rating > 0).then(|| (rating - 1) as f32 * 10.0 / 254.0)
+}
+
+/// returns stats from popularimeter and play counter frames.
+/// popularimeter written by mscout has higher priority over other players, rating of other
+/// players is taken as manual rating
+fn stats_from_frames(tag: &Tag) -> Option<Statistics> {
+    let mut popularimeters: Vec<&Popularimeter> = tag
+        .frames()
+        .filter_map(|frame| frame.content().popularimeter())
+        .collect();
+    popularimeters.sort_by_key(|popm| popm.user != MP_DESC);
+    if let Some(popm) = popularimeters.first() {
+        debug!("found popularimeter {}", popm);
+        let play_cnt = match popm.counter {
+            // some players doesn't count the plays in popularimeter
+            0 => play_count_from_counter(tag).unwrap_or_default(),
+            counter => counter.min(u32::MAX as u64) as u32,
+        };
+        return Some(Statistics {
+            play_cnt,
+            user_rating: (popm.user != MP_DESC)
+                .then(|| rating_from_popularimeter(popm.rating))
+                .flatten(),
+            ..Default::default()
+        });
+    }
+    play_count_from_counter(tag).map(|play_cnt| Statistics {
+        play_cnt,
+        ..Default::default()
+    })
+}
+
+/// returns play count from play counter frame
+fn play_count_from_counter(tag: &Tag) -> Option<u32> {
+    let counter = tag.get(PLAY_COUNTER_ID)?.content().to_unknown().ok()?;
+    debug!("found play counter {:?}", counter.data);
+    // counter is big endian integer of at least 4 bytes
+    let count = counter
+        .data
+        .iter()
+        .fold(0u64, |count, byte| count.saturating_mul(256) | *byte as u64);
+    Some(count.min(u32::MAX as u64) as u32)
+}
+
+/// returns the stats from `msout` comment, if comment is not present then play count and rating
+/// are taken from standard frames. If tag or these frames are not present then None is returned
+pub fn read(song_path: &Path) -> Result<Option<Statistics>, Error> {
+    let tag = match Tag::read_from_path(song_path) {
+        Ok(tag) => tag,
+        Err(id3::Error {
+            kind: id3::ErrorKind::NoTag,
+            ..
+        }) => {
+            warn!("no id3 tag found for song {:?}", song_path);
+            return Ok(None);
+        }
+        Err(
+            err @ id3::Error {
+                kind: id3::ErrorKind::StringDecoding(..),
+                ..
+            },
+        ) => {
+            error!(
+                "invalid input error while reading tag {:?} for song {:?}",
+                err.description, song_path,
+            );
+            return Err(Error::Id3ReadTag);
+        }
+        Err(err) => {
+            error!(
+                "unknown error while reading tag {:?} for song {:?}",
+                err.description, song_path,
+            );
+            return Err(Error::Unknown);
+        }
+    };
+    for com in tag.comments() {
+        debug!("available comments are {:?}", com);
+        if com.description == MP_DESC {
+            return Ok(Some(parse_stats(&com.text)));
+        }
+    }
+    Ok(stats_from_frames(&tag))
+}
+
+/// reads the tag of the song, if the song doesn't have tag then new tag is created
+fn read_or_new(song_path: &Path) -> Result<Tag, Error> {
+    Tag::read_from_path(song_path).or_else(|err: id3::Error| match err.kind {
+        id3::ErrorKind::NoTag => {
+            warn!("no tag found creating a new id3 tag");
+            Ok(Tag::new())
+        }
+        _ => {
+            error!(" error while opening tag {:?}", err.description);
+            Err(Error::FileNotExists)
+        }
+    })
+}
+
+/// removes popularimeter written by mscout
+fn remove_popularimeter(tag: &mut Tag) {
+    let others: Vec<Frame> = tag
+        .remove(POPULARIMETER_ID)
+        .into_iter()
+        .filter(|frame| {
+            frame
+                .content()
+                .popularimeter()
+                .is_none_or(|popm| popm.user != MP_DESC)
+        })
+        .collect();
+    for frame in others {
+        tag.add_frame(frame);
+    }
+}
+
+/// sets `text` to `msout` comment of the song, if `stats` are given then popularimeter and play
+/// counter frames are updated as well
+pub fn write(song_path: &Path, text: String, stats: Option<&Statistics>) -> Result<(), Error> {
+    let mut tag = read_or_new(song_path)?;
+    let comment: Comment = Comment {
+        lang: "eng".to_string(),
+        description: MP_DESC.to_string(),
+        text,
+    };
+    info!("attaching tag comment {:?}", comment);
+    tag.add_frame(comment);
+    if let Some(stats) = stats {
+        let popm = Popularimeter {
+            user: MP_DESC.to_string(),
+            rating: popularimeter_rating(stats),
+            counter: stats.play_cnt as u64,
+        };
+        info!("attaching popularimeter {}", popm);
+        remove_popularimeter(&mut tag);
+        tag.add_frame(popm);
+        // unknown frames are never replaced, so old counter has to be removed
+        tag.remove(PLAY_COUNTER_ID);
+        tag.add_frame(Frame::with_content(
+            PLAY_COUNTER_ID,
+            Content::Unknown(Unknown {
+                data: stats.play_cnt.to_be_bytes().to_vec(),
+                version: id3::Version::Id3v24,
+            }),
+        ));
+    }
+    tag.write_to_path(song_path, id3::Version::Id3v24)
+        .unwrap_or_else(|err| warn!("failed to write tag {}", err));
+    Ok(())
+}
+
+/// removes the `msout` comment and mscout's popularimeter from the tags, if `play_counter` is set
+/// then play counter frame is removed as well
+pub fn remove(song_path: &Path, play_counter: bool) -> Result<(), Error> {
+    let mut tag = match Tag::read_from_path(song_path) {
+        Ok(tag) => tag,
+        // nothing to remove
+        Err(id3::Error {
+            kind: id3::ErrorKind::NoTag,
+            ..
+        }) => return Ok(()),
+        Err(err) => {
+            error!(" error while opening tag {:?}", err.description);
+            return Err(Error::FileNotExists);
+        }
+    };
+    tag.remove_comment(Some(MP_DESC), None);
+    remove_popularimeter(&mut tag);
+    if play_counter {
+        tag.remove(PLAY_COUNTER_ID);
+    }
+    tag.write_to_path(song_path, id3::Version::Id3v24)
+        .unwrap_or_else(|err| warn!("failed to write tag {}", err));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// creates a file without tags in temporary directory
+    fn temp_song(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("mscout-{}-{name}.mp3", std::process::id()));
+        std::fs::write(&path, [0xff, 0xfb, 0x90, 0x00]).unwrap();
+        path
+    }
+
+    #[test]
+    fn play_counter_is_replaced() {
+        let path = temp_song("pcnt");
+        for play_cnt in [3, 4] {
+            let stats = Statistics {
+                play_cnt,
+                ..Default::default()
+            };
+            write(&path, serde_json::to_string(&stats).unwrap(), Some(&stats)).unwrap();
+        }
+        let tag = Tag::read_from_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let counters: Vec<_> = tag
+            .frames()
+            .filter(|frame| frame.id() == PLAY_COUNTER_ID)
+            .collect();
+        assert_eq!(counters.len(), 1);
+        assert_eq!(play_count_from_counter(&tag), Some(4));
+        assert_eq!(
+            tag.frames()
+                .filter(|frame| frame.id() == POPULARIMETER_ID)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn rating_of_other_players() {
+        let mut tag = Tag::new();
+        tag.add_frame(Popularimeter {
+            user: "no@email".to_string(),
+            rating: 255,
+            counter: 7,
+        });
+        let stats = stats_from_frames(&tag).unwrap();
+        assert_eq!(stats.play_cnt, 7);
+        assert_eq!(stats.user_rating, Some(10.0));
+        // rating is converted back to same popularimeter rating
+        for rating in [1, 64, 128, 196, 255] {
+            let stats = Statistics {
+                user_rating: rating_from_popularimeter(rating),
+                ..Default::default()
+            };
+            assert_eq!(popularimeter_rating(&stats), rating);
+        }
+        assert_eq!(rating_from_popularimeter(0), None);
+    }
+
+    #[test]
+    fn own_popularimeter_is_not_manual_rating() {
+        let mut tag = Tag::new();
+        tag.add_frame(Popularimeter {
+            user: MP_DESC.to_string(),
+            rating: 200,
+            counter: 2,
+        });
+        let stats = stats_from_frames(&tag).unwrap();
+        assert_eq!(stats.play_cnt, 2);
+        assert_eq!(stats.user_rating, None);
+    }
+}
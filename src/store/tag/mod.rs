@@ -0,0 +1,199 @@
+//! stores statistics in the metadata of the song file
+mod hash;
+mod id3v2;
+mod mp4;
+mod vorbis;
+
+use super::StatsStore;
+use crate::{error::Error, stats::Statistics, ConnType, ROOT_DIR};
+use log::{debug, error, trace, warn};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+pub use hash::audio_hash;
+
+/// returns the full path of the song, if the given path is not a file then it is considered as
+/// relative to mpd's music directory
+fn full_path(song: &Path) -> PathBuf {
+    if song.is_file() {
+        PathBuf::from(song)
+    } else {
+        PathBuf::from(ROOT_DIR.get().expect("statistics to tag requires full path, try to use --socket-file or set root-dir manually")).join(song)
+    }
+}
+
+/// parses the stats stored in the tags, invalid stats are reset to 0,0
+fn parse_stats(text: &str) -> Statistics {
+    serde_json::from_str(text).unwrap_or_else(|err| {
+        warn!("err {} invalid json text for rating comment {}", err, text);
+        Statistics::default()
+    })
+}
+
+/// container format of the song which decides how the tags are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    /// id3v2 tags, used for mp3 and any other unknown formats
+    Id3,
+    /// flac with vorbis comments
+    Flac,
+    /// ogg vorbis or opus with vorbis comments
+    Ogg,
+    /// mp4/m4a with freeform atom
+    Mp4,
+}
+
+impl Container {
+    /// detects the container from magic bytes of the file
+    fn detect(song_path: &Path) -> Result<Self, Error> {
+        let mut file = std::fs::File::open(song_path).map_err(|err| {
+            error!("couldn't open {:?}, due to {}", song_path, err);
+            Error::FileNotExists
+        })?;
+        let mut header = Vec::with_capacity(10);
+        file.by_ref()
+            .take(10)
+            .read_to_end(&mut header)
+            .map_err(|_| Error::FileNotExists)?;
+        let container = if header.starts_with(b"fLaC") {
+            Container::Flac
+        } else if header.starts_with(b"OggS") {
+            Container::Ogg
+        } else if header.get(4..8) == Some(b"ftyp") {
+            Container::Mp4
+        } else if vorbis::id3_prefix_len(&header) > 0 {
+            // some taggers prepend id3 tag to flac files
+            let mut file = std::io::BufReader::new(file);
+            std::io::copy(
+                &mut file
+                    .by_ref()
+                    .take((vorbis::id3_prefix_len(&header) - header.len()) as u64),
+                &mut std::io::sink(),
+            )
+            .map_err(|_| Error::FileNotExists)?;
+            let mut magic = [0; 4];
+            if file.read_exact(&mut magic).is_ok() && &magic == b"fLaC" {
+                Container::Flac
+            } else {
+                Container::Id3
+            }
+        } else {
+            Container::Id3
+        };
+        debug!("container of {:?} is {:?}", song_path, container);
+        Ok(container)
+    }
+
+    /// returns stats stored in the song's tags, None if song doesn't have any stats
+    fn read(&self, song_path: &Path) -> Result<Option<Statistics>, Error> {
+        let text = match self {
+            Container::Id3 => return id3v2::read(song_path),
+            Container::Flac => vorbis::read_flac(song_path)?,
+            Container::Ogg => vorbis::read_ogg(song_path)?,
+            Container::Mp4 => mp4::read(song_path)?,
+        };
+        Ok(text.as_deref().map(parse_stats))
+    }
+
+    /// writes the stats to song's tags, `id3_frames` enables standard id3 frames
+    fn write(&self, song_path: &Path, stats: &Statistics, id3_frames: bool) -> Result<(), Error> {
+        let text = serde_json::to_string(stats).expect("couldn't convert ratings  to json");
+        match self {
+            Container::Id3 => id3v2::write(song_path, text, id3_frames.then_some(stats)),
+            Container::Flac => vorbis::write_flac(song_path, Some(&text)),
+            Container::Ogg => vorbis::write_ogg(song_path, Some(&text)),
+            Container::Mp4 => mp4::write(song_path, Some(text)),
+        }
+    }
+
+    /// removes the stats from song's tags, `id3_frames` removes standard id3 play counter as well
+    fn remove(&self, song_path: &Path, id3_frames: bool) -> Result<(), Error> {
+        match self {
+            Container::Id3 => id3v2::remove(song_path, id3_frames),
+            Container::Flac => vorbis::write_flac(song_path, None),
+            Container::Ogg => vorbis::write_ogg(song_path, None),
+            Container::Mp4 => mp4::write(song_path, None),
+        }
+    }
+}
+
+/// tag backend, stats are stored as json in the `msout` field of the song's metadata.
+/// For id3 it is a comment with description `msout`, for flac and ogg it is a vorbis comment field
+/// and for mp4 it is a freeform atom `----:com.mscout:stats`.
+#[derive(Debug, Default)]
+pub struct TagStore {
+    /// write standard id3 POPM and PCNT frames along with the comment
+    id3_frames: bool,
+}
+
+impl TagStore {
+    /// creates tag backend, if `id3_frames` is set then standard id3 frames are written as well
+    pub fn new(id3_frames: bool) -> Self {
+        Self { id3_frames }
+    }
+
+    /// returns the statistics from the song's tags, None if the song doesn't have any
+    fn read_tag(&self, rel_path: &Path) -> Result<Option<Statistics>, Error> {
+        let song_pbuff = full_path(rel_path);
+        debug!("songs full path is {:#?}", song_pbuff);
+        Container::detect(&song_pbuff)?.read(&song_pbuff)
+    }
+}
+
+impl StatsStore for TagStore {
+    /// extracts the statistics from the song's tags, songs without stats are not modified and
+    /// default stats are returned.
+    fn read(
+        &mut self,
+        _client: &mut mpd::Client<ConnType>,
+        rel_path: &Path,
+    ) -> Result<Statistics, Error> {
+        Ok(self.read_tag(rel_path)?.unwrap_or_default())
+    }
+
+    /// set the statistics to the song's tags.
+    fn write(
+        &mut self,
+        _client: &mut mpd::Client<ConnType>,
+        spath: &Path,
+        stats: &Statistics,
+    ) -> Result<(), Error> {
+        let song_pbuff = full_path(spath);
+        debug!("setting tag to {:#?}", song_pbuff);
+        Container::detect(&song_pbuff)?.write(&song_pbuff, stats, self.id3_frames)
+    }
+
+    /// removes the stats from the song's tags
+    fn delete(&mut self, _client: &mut mpd::Client<ConnType>, spath: &Path) -> Result<(), Error> {
+        let song_pbuff = full_path(spath);
+        debug!("removing stats from tag of {:#?}", song_pbuff);
+        Container::detect(&song_pbuff)?.remove(&song_pbuff, self.id3_frames)
+    }
+
+    /// tags can't be queried, so reads tag of every song in mpd's database, songs without stats
+    /// are skipped
+    fn list(
+        &mut self,
+        client: &mut mpd::Client<ConnType>,
+    ) -> Result<Vec<(PathBuf, Statistics)>, Error> {
+        let songs = client.listall().map_err(|err| {
+            error!("Couldn't list songs due to {:?}", err);
+            Error::ConnectionFailed
+        })?;
+        let mut stats = Vec::with_capacity(songs.len());
+        for song in songs {
+            let spath = PathBuf::from(song.file);
+            match self.read_tag(&spath) {
+                Ok(Some(stat)) => stats.push((spath, stat)),
+                Ok(None) => trace!("{:?} doesn't have stats", spath),
+                Err(Error::Id3ReadTag | Error::VorbisReadTag | Error::Mp4ReadTag) => {
+                    warn!("skipping {:?}", spath)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(stats)
+    }
+}
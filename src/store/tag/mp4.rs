@@ -0,0 +1,41 @@
+//! reads and writes stats from mp4/m4a metadata.
+//! stats are stored as json in freeform atom `----:com.mscout:stats`.
+use crate::error::Error;
+use log::{error, info, warn};
+use mp4ameta::{Data, FreeformIdent, Tag};
+use std::path::Path;
+
+/// identifier of the freeform atom where stats are stored
+const STATS_IDENT: FreeformIdent<'static> = FreeformIdent::new("com.mscout", "stats");
+
+/// reads the mp4 metadata of the song
+fn read_tag(song_path: &Path) -> Result<Tag, Error> {
+    Tag::read_from_path(song_path).map_err(|err| {
+        error!(
+            "error while reading mp4 tag {:?} for song {:?}",
+            err, song_path
+        );
+        Error::Mp4ReadTag
+    })
+}
+
+/// returns the content of stats atom, None if the atom is not present
+pub fn read(song_path: &Path) -> Result<Option<String>, Error> {
+    let tag = read_tag(song_path)?;
+    let stats = tag.strings_of(&STATS_IDENT).next().map(str::to_owned);
+    Ok(stats)
+}
+
+/// sets the stats atom, if text is None then atom is removed
+pub fn write(song_path: &Path, text: Option<String>) -> Result<(), Error> {
+    let mut tag = read_tag(song_path)?;
+    info!("setting mp4 atom {:?} to {:?}", text, song_path);
+    match text {
+        Some(text) => tag.set_data(STATS_IDENT, Data::Utf8(text)),
+        None => tag.remove_data_of(&STATS_IDENT),
+    }
+    tag.write_to_path(song_path).map_err(|err| {
+        warn!("failed to write mp4 tag to {:?}: {:?}", song_path, err);
+        Error::FileNotExists
+    })
+}
@@ -0,0 +1,388 @@
+//! reads and writes stats from vorbis comments of flac and ogg(vorbis, opus) containers.
+//! stats are stored as json in `msout` field of the comment.
+use crate::{error::Error, MP_DESC};
+use log::{debug, error, info, warn};
+use ogg::{PacketReader, PacketWriteEndInfo, PacketWriter};
+use std::{
+    collections::HashSet,
+    io::{Cursor, Read, Write},
+    path::Path,
+};
+
+/// metadata block type of vorbis comment in flac
+const FLAC_VORBIS_COMMENT: u8 = 4;
+/// comment header prefix of ogg vorbis stream
+const VORBIS_COMMENT_MAGIC: &[u8] = b"\x03vorbis";
+/// comment header prefix of ogg opus stream
+const OPUS_COMMENT_MAGIC: &[u8] = b"OpusTags";
+
+/// Vorbis comment block, this is same for flac and ogg containers
+#[derive(Debug)]
+struct VorbisComment {
+    /// vendor string of the encoder
+    vendor: Vec<u8>,
+    /// raw comments in the form of `FIELD=value`
+    comments: Vec<Vec<u8>>,
+}
+
+/// reads little endian u32 from data at `pos` and advances `pos`
+fn read_u32(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let bytes = data.get(*pos..*pos + 4)?;
+    *pos += 4;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// reads length prefixed bytes from data at `pos` and advances `pos`
+fn read_bytes(data: &[u8], pos: &mut usize) -> Option<Vec<u8>> {
+    let len = read_u32(data, pos)? as usize;
+    let bytes = data.get(*pos..pos.checked_add(len)?)?;
+    *pos += len;
+    Some(bytes.to_vec())
+}
+
+impl VorbisComment {
+    /// parses the comment block, returns the comment and number of bytes consumed from data
+    fn parse(data: &[u8]) -> Option<(Self, usize)> {
+        let mut pos = 0;
+        let vendor = read_bytes(data, &mut pos)?;
+        let count = read_u32(data, &mut pos)?;
+        let mut comments = Vec::new();
+        for _ in 0..count {
+            comments.push(read_bytes(data, &mut pos)?);
+        }
+        Some((Self { vendor, comments }, pos))
+    }
+
+    /// serializes the comment block
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(self.vendor.len() as u32).to_le_bytes());
+        data.extend_from_slice(&self.vendor);
+        data.extend_from_slice(&(self.comments.len() as u32).to_le_bytes());
+        for comment in &self.comments {
+            data.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            data.extend_from_slice(comment);
+        }
+        data
+    }
+
+    /// returns true if the comment belongs to field, field names are case insensitive
+    fn is_field(comment: &[u8], field: &str) -> bool {
+        comment.len() > field.len()
+            && comment[field.len()] == b'='
+            && comment[..field.len()].eq_ignore_ascii_case(field.as_bytes())
+    }
+
+    /// returns the value of first comment with given field
+    fn get(&self, field: &str) -> Option<&str> {
+        self.comments
+            .iter()
+            .find(|comment| Self::is_field(comment, field))
+            .and_then(|comment| std::str::from_utf8(&comment[field.len() + 1..]).ok())
+    }
+
+    /// replaces all the comments of field with given value, if value is None then field is removed
+    fn set(&mut self, field: &str, value: Option<&str>) {
+        self.comments
+            .retain(|comment| !Self::is_field(comment, field));
+        if let Some(value) = value {
+            self.comments
+                .push(format!("{}={}", field.to_ascii_uppercase(), value).into_bytes());
+        }
+    }
+}
+
+/// returns the length of id3v2 header and its content if the data starts with id3 tag.
+/// some taggers prepend id3 tag to flac files, these bytes are left untouched.
+pub fn id3_prefix_len(header: &[u8]) -> usize {
+    if header.len() < 10 || &header[..3] != b"ID3" {
+        return 0;
+    }
+    // tag size is stored as syncsafe integer
+    let size = header[6..10]
+        .iter()
+        .fold(0, |size, byte| (size << 7) | (*byte as usize & 0x7f));
+    // footer flag adds another 10 bytes
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    10 + size + footer
+}
+
+/// flac file split into parts
+pub(super) struct FlacFile {
+    /// bytes before `fLaC` marker, usually id3 tag
+    prefix: Vec<u8>,
+    /// metadata blocks with its type
+    blocks: Vec<(u8, Vec<u8>)>,
+    /// audio frames, this is empty if only metadata is read
+    frames: Vec<u8>,
+}
+
+impl FlacFile {
+    /// reads the metadata blocks from the reader, if `with_frames` is set then audio frames are
+    /// read as well
+    fn read<R: Read>(mut reader: R, with_frames: bool) -> std::io::Result<Option<Self>> {
+        let mut header = [0; 10];
+        reader.read_exact(&mut header[..4])?;
+        let mut prefix = Vec::new();
+        if &header[..3] == b"ID3" {
+            reader.read_exact(&mut header[4..])?;
+            prefix.extend_from_slice(&header);
+            reader
+                .by_ref()
+                .take((id3_prefix_len(&header) - 10) as u64)
+                .read_to_end(&mut prefix)?;
+            reader.read_exact(&mut header[..4])?;
+        }
+        if &header[..4] != b"fLaC" {
+            return Ok(None);
+        }
+        let mut blocks = Vec::new();
+        loop {
+            let mut block_header = [0; 4];
+            reader.read_exact(&mut block_header)?;
+            let len = u32::from_be_bytes([0, block_header[1], block_header[2], block_header[3]]);
+            let mut block = Vec::with_capacity(len as usize);
+            reader.by_ref().take(len as u64).read_to_end(&mut block)?;
+            blocks.push((block_header[0] & 0x7f, block));
+            // last metadata block flag
+            if block_header[0] & 0x80 != 0 {
+                break;
+            }
+        }
+        let mut frames = Vec::new();
+        if with_frames {
+            reader.read_to_end(&mut frames)?;
+        }
+        Ok(Some(Self {
+            prefix,
+            blocks,
+            frames,
+        }))
+    }
+
+    /// serializes the flac file
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = self.prefix.clone();
+        data.extend_from_slice(b"fLaC");
+        for (idx, (block_type, block)) in self.blocks.iter().enumerate() {
+            let mut block_header = (block.len() as u32).to_be_bytes();
+            block_header[0] = *block_type;
+            if idx + 1 == self.blocks.len() {
+                block_header[0] |= 0x80;
+            }
+            data.extend_from_slice(&block_header);
+            data.extend_from_slice(block);
+        }
+        data.extend_from_slice(&self.frames);
+        data
+    }
+
+    /// returns the number of bytes before audio frames
+    pub(super) fn metadata_len(&self) -> u64 {
+        let blocks: usize = self.blocks.iter().map(|(_, block)| 4 + block.len()).sum();
+        (self.prefix.len() + 4 + blocks) as u64
+    }
+
+    /// returns the vorbis comment of the flac
+    fn comment(&self) -> Option<VorbisComment> {
+        self.blocks
+            .iter()
+            .find(|(block_type, _)| *block_type == FLAC_VORBIS_COMMENT)
+            .and_then(|(_, block)| VorbisComment::parse(block))
+            .map(|(comment, _)| comment)
+    }
+}
+
+/// opens flac file, if `with_frames` is not set then only metadata is read
+pub(super) fn open_flac(song_path: &Path, with_frames: bool) -> Result<FlacFile, Error> {
+    let file = std::fs::File::open(song_path).map_err(|err| {
+        error!("couldn't open {:?}, due to {}", song_path, err);
+        Error::FileNotExists
+    })?;
+    match FlacFile::read(std::io::BufReader::new(file), with_frames) {
+        Ok(Some(flac)) => Ok(flac),
+        Ok(None) => {
+            error!("{:?} is not a valid flac file", song_path);
+            Err(Error::VorbisReadTag)
+        }
+        Err(err) => {
+            error!(
+                "error while reading flac metadata {} for song {:?}",
+                err, song_path
+            );
+            Err(Error::VorbisReadTag)
+        }
+    }
+}
+
+/// returns `msout` field of flac's vorbis comment, None if the field or comment is not present
+pub fn read_flac(song_path: &Path) -> Result<Option<String>, Error> {
+    let flac = open_flac(song_path, false)?;
+    Ok(flac
+        .comment()
+        .and_then(|comment| comment.get(MP_DESC).map(str::to_owned)))
+}
+
+/// sets `msout` field of flac's vorbis comment, if text is None then field is removed
+pub fn write_flac(song_path: &Path, text: Option<&str>) -> Result<(), Error> {
+    let mut flac = open_flac(song_path, true)?;
+    let mut comment = flac.comment().unwrap_or_else(|| {
+        debug!("no vorbis comment found creating a new one");
+        VorbisComment {
+            vendor: b"mscout".to_vec(),
+            comments: Vec::new(),
+        }
+    });
+    comment.set(MP_DESC, text);
+    info!("setting vorbis comment {:?} to {:?}", text, song_path);
+    let block = comment.to_bytes();
+    if let Some((_, old)) = flac
+        .blocks
+        .iter_mut()
+        .find(|(block_type, _)| *block_type == FLAC_VORBIS_COMMENT)
+    {
+        *old = block;
+    } else {
+        // first block must always be STREAMINFO
+        flac.blocks
+            .insert(1.min(flac.blocks.len()), (FLAC_VORBIS_COMMENT, block));
+    }
+    write_atomic(song_path, &flac.to_bytes())
+}
+
+/// replaces the content of the song with `data`. Data is written to a temporary file in the same
+/// directory which is then renamed over the song, so the song is not truncated if writing fails.
+fn write_atomic(song_path: &Path, data: &[u8]) -> Result<(), Error> {
+    let io_error = |err: std::io::Error| {
+        warn!("failed to write vorbis comment to {:?}: {}", song_path, err);
+        Error::FileNotExists
+    };
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(song_path.file_name().unwrap_or_default());
+    tmp_name.push(".mscout.tmp");
+    let tmp_path = song_path.with_file_name(tmp_name);
+    let written = (|| {
+        let mut tmp = std::fs::File::create(&tmp_path)?;
+        tmp.write_all(data)?;
+        tmp.set_permissions(std::fs::metadata(song_path)?.permissions())?;
+        tmp.sync_all()?;
+        std::fs::rename(&tmp_path, song_path)
+    })();
+    if let Err(err) = written {
+        std::fs::remove_file(&tmp_path).ok();
+        return Err(io_error(err));
+    }
+    // rename is persisted only after the directory is synced
+    if let Some(dir) = song_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .unwrap_or_else(|err| debug!("couldn't sync {:?}: {}", dir, err));
+    }
+    Ok(())
+}
+
+/// splits ogg comment header packet into magic, comment and trailing bytes of the packet
+fn split_ogg_comment(packet: &[u8]) -> Option<(&[u8], VorbisComment, &[u8])> {
+    let magic = [VORBIS_COMMENT_MAGIC, OPUS_COMMENT_MAGIC]
+        .into_iter()
+        .find(|magic| packet.starts_with(magic))?;
+    let (comment, len) = VorbisComment::parse(&packet[magic.len()..])?;
+    Some((magic, comment, &packet[magic.len() + len..]))
+}
+
+/// reads the packets of the ogg file
+fn read_ogg_packets(data: Vec<u8>) -> Result<Vec<ogg::Packet>, ogg::OggReadError> {
+    let mut reader = PacketReader::new(Cursor::new(data));
+    let mut packets = Vec::new();
+    while let Some(packet) = reader.read_packet()? {
+        packets.push(packet);
+    }
+    Ok(packets)
+}
+
+/// reads the ogg file, comment header is always second packet of the logical stream
+pub(super) fn open_ogg(song_path: &Path) -> Result<Vec<ogg::Packet>, Error> {
+    let data = std::fs::read(song_path).map_err(|err| {
+        error!("couldn't open {:?}, due to {}", song_path, err);
+        Error::FileNotExists
+    })?;
+    read_ogg_packets(data).map_err(|err| {
+        error!(
+            "error while reading ogg packets {:?} for song {:?}",
+            err, song_path
+        );
+        Error::VorbisReadTag
+    })
+}
+
+/// returns `msout` field of ogg's vorbis comment, None if the field is not present.
+/// Only the packets till the comment header are read.
+pub fn read_ogg(song_path: &Path) -> Result<Option<String>, Error> {
+    let file = std::fs::File::open(song_path).map_err(|err| {
+        error!("couldn't open {:?}, due to {}", song_path, err);
+        Error::FileNotExists
+    })?;
+    let mut reader = PacketReader::new(std::io::BufReader::new(file));
+    let mut seen_streams = HashSet::new();
+    while let Some(packet) = reader.read_packet().map_err(|err| {
+        error!(
+            "error while reading ogg packets {:?} for song {:?}",
+            err, song_path
+        );
+        Error::VorbisReadTag
+    })? {
+        // first packet is identification header, second one is comment
+        if seen_streams.insert(packet.stream_serial()) {
+            continue;
+        }
+        return match split_ogg_comment(&packet.data) {
+            Some((_, comment, _)) => Ok(comment.get(MP_DESC).map(str::to_owned)),
+            None => {
+                error!("unsupported ogg stream in {:?}", song_path);
+                Err(Error::VorbisReadTag)
+            }
+        };
+    }
+    warn!("no comment header found in {:?}", song_path);
+    Ok(None)
+}
+
+/// sets `msout` field of ogg's vorbis comment, if text is None then field is removed.
+/// Whole file is rewritten with the same pages except comment header.
+pub fn write_ogg(song_path: &Path, text: Option<&str>) -> Result<(), Error> {
+    let packets = open_ogg(song_path)?;
+    let mut writer = PacketWriter::new(Vec::new());
+    let mut packet_counts = std::collections::HashMap::new();
+    for packet in packets {
+        let serial = packet.stream_serial();
+        let count = packet_counts.entry(serial).or_insert(0);
+        *count += 1;
+        let end_info = if packet.last_in_stream() {
+            PacketWriteEndInfo::EndStream
+        } else if packet.last_in_page() {
+            PacketWriteEndInfo::EndPage
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+        let absgp = packet.absgp_page();
+        let data = if *count == 2 {
+            let (magic, mut comment, trailing) =
+                split_ogg_comment(&packet.data).ok_or_else(|| {
+                    error!("unsupported ogg stream in {:?}", song_path);
+                    Error::VorbisReadTag
+                })?;
+            comment.set(MP_DESC, text);
+            info!("setting vorbis comment {:?} to {:?}", text, song_path);
+            [magic, &comment.to_bytes(), trailing].concat()
+        } else {
+            packet.data
+        };
+        writer
+            .write_packet(data.into_boxed_slice(), serial, end_info, absgp)
+            .map_err(|err| {
+                error!("failed to write ogg packet: {}", err);
+                Error::Unknown
+            })?;
+    }
+    write_atomic(song_path, &writer.into_inner())
+}